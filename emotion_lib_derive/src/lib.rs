@@ -1,6 +1,6 @@
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, ItemFn};
+use syn::{parse_macro_input, ItemFn, LitStr};
 
 #[proc_macro_attribute]
 pub fn ensure_event(_attr: TokenStream, item: TokenStream) -> TokenStream {
@@ -26,7 +26,7 @@ fn ensure_event_impl(input: TokenStream) -> TokenStream {
         
         #(#attrs)*
         #vis #sig {
-            let __user = match emotionLib::auth::get_user(&req, &data.db).await {
+            let __user = match emotionLib::auth::get_user(&req, &data.db, &data.config, &data.limiter).await {
                 Ok(u) => u,
                 Err(e) => return e
             };
@@ -41,13 +41,10 @@ fn ensure_event_impl(input: TokenStream) -> TokenStream {
                 Err(e) => return e
             };
 
-            let __event_con_clone;
-            {
-                __event_con_clone = match data.event_cons.lock().unwrap().get(&event.id) {
-                    Some(c) => c.clone(),
-                    None => return HttpResponse::NotFound().into()
-                };
-            }
+            let __event_con_clone = match data.event_cons.get(&event.id).await {
+                Ok(c) => c,
+                Err(e) => return e
+            };
             let event_con = &__event_con_clone;
 
             #(#statements)*
@@ -80,7 +77,7 @@ fn ensure_user_impl(input: TokenStream) -> TokenStream {
         
         #(#attrs)*
         #vis #sig {
-            let user = match emotionLib::auth::get_user(&req, &data.db).await {
+            let user = match emotionLib::auth::get_user(&req, &data.db, &data.config, &data.limiter).await {
                 Ok(u) => u,
                 Err(e) => return e
             };
@@ -115,13 +112,13 @@ fn ensure_admin_impl(input: TokenStream) -> TokenStream {
         
         #(#attrs)*
         #vis #sig {
-            let user = match emotionLib::auth::get_user(&req, &data.db).await {
+            let user = match emotionLib::auth::get_user(&req, &data.db, &data.config, &data.limiter).await {
                 Ok(u) => u,
                 Err(e) => return e
             };
 
-            if(matches!(user, AuthUser::TmpUser{..}) || matches!(user, AuthUser::NotApprovedTmpUser{..})) {
-                return HttpResponse::Forbidden().into(); 
+            if let Err(e) = emotionLib::auth::roles::check_admin(&user, &data.db).await {
+                return e;
             }
 
             #(#statements)*
@@ -130,3 +127,46 @@ fn ensure_admin_impl(input: TokenStream) -> TokenStream {
     .into()
 }
 
+
+
+#[proc_macro_attribute]
+pub fn ensure_permission(attr: TokenStream, item: TokenStream) -> TokenStream {
+    ensure_permission_impl(attr, item)
+}
+
+fn ensure_permission_impl(attr: TokenStream, input: TokenStream) -> TokenStream {
+    // the permission is checked by `roles::check_permission`, so there is only one list of them
+    let permission = parse_macro_input!(attr as LitStr);
+    let input = parse_macro_input!(input as ItemFn);
+
+    let ItemFn {
+        sig,
+        vis,
+        block,
+        attrs,
+    } = input;
+
+    let statements = block.stmts;
+
+    // Reconstruct the function as output using parsed input
+    quote!(
+        // Reapply all the other attributes on this function.
+        // The compiler doesn't include the macro we are
+        
+        #(#attrs)*
+        #vis #sig {
+            let user = match emotionLib::auth::get_user(&req, &data.db, &data.config, &data.limiter).await {
+                Ok(u) => u,
+                Err(e) => return e
+            };
+
+            let role = match emotionLib::auth::roles::check_permission(&user, #permission, &data.db).await {
+                Ok(r) => r,
+                Err(e) => return e
+            };
+
+            #(#statements)*
+        }
+    )
+    .into()
+}
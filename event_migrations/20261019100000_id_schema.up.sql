@@ -0,0 +1,9 @@
+-- the id schema of the students, there is at most one row
+CREATE TABLE id_schema(
+    id INT NOT NULL,
+    min_id INT NOT NULL,
+    max_id INT NOT NULL,
+    check_digit boolean NOT NULL,
+    use_external_ids boolean NOT NULL,
+    PRIMARY KEY (id)
+);
@@ -0,0 +1,12 @@
+-- sqlite can't drop a column with a foreign key, so the table is copied without it
+CREATE TABLE user_session_old (
+    api_key VARCHAR(512) NOT NULL,
+    time_of_creation INT NOT NULL,
+    last_refresh INT NOT NULL,
+    PRIMARY KEY (api_key)
+);
+INSERT INTO user_session_old(api_key, time_of_creation, last_refresh)
+    SELECT api_key, time_of_creation, last_refresh FROM user_session;
+DROP TABLE user_session;
+ALTER TABLE user_session_old RENAME TO user_session;
+DROP TABLE admin_user;
@@ -0,0 +1,9 @@
+CREATE TABLE admin_user (
+    username VARCHAR(64) PRIMARY KEY NOT NULL,
+    password_hash VARCHAR(255) NOT NULL,
+    time_of_creation INT NOT NULL,
+    time_of_password_change INT NOT NULL
+);
+
+-- sessions from before the login have no user and are not valid anymore
+ALTER TABLE user_session ADD COLUMN username VARCHAR(64) REFERENCES admin_user(username);
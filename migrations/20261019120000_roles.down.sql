@@ -0,0 +1,5 @@
+ALTER TABLE tmp_user DROP COLUMN role;
+ALTER TABLE admin_user DROP COLUMN klasse;
+ALTER TABLE admin_user DROP COLUMN role;
+DROP TABLE role_permission;
+DROP TABLE role;
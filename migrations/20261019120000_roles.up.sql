@@ -0,0 +1,31 @@
+CREATE TABLE role (
+    name VARCHAR(64) PRIMARY KEY NOT NULL
+);
+
+CREATE TABLE role_permission (
+    role VARCHAR(64) NOT NULL,
+    permission VARCHAR(64) NOT NULL,
+    PRIMARY KEY (role, permission),
+    FOREIGN KEY (role) REFERENCES role(name) ON DELETE CASCADE
+);
+
+INSERT INTO role(name) VALUES ('admin'), ('head_teacher'), ('class_teacher'), ('helper');
+INSERT INTO role_permission(role, permission) VALUES
+    ('admin', 'manage_events'),
+    ('admin', 'upload_students'),
+    ('admin', 'record_attempts'),
+    ('admin', 'view_results'),
+    ('admin', 'export'),
+    ('head_teacher', 'upload_students'),
+    ('head_teacher', 'record_attempts'),
+    ('head_teacher', 'view_results'),
+    ('head_teacher', 'export'),
+    ('class_teacher', 'view_results'),
+    ('class_teacher', 'export'),
+    ('helper', 'record_attempts');
+
+-- existing admins keep all their rights
+ALTER TABLE admin_user ADD COLUMN role VARCHAR(64) NOT NULL DEFAULT 'admin';
+-- if set the user may only see this class
+ALTER TABLE admin_user ADD COLUMN klasse VARCHAR(10);
+ALTER TABLE tmp_user ADD COLUMN role VARCHAR(64) NOT NULL DEFAULT 'helper';
@@ -0,0 +1,7 @@
+-- the kategorien a tmp user may record attempts for, no rows means all
+CREATE TABLE tmp_user_station (
+    tmp_user_id VARCHAR(10) NOT NULL,
+    kategorie_id INT NOT NULL,
+    PRIMARY KEY (tmp_user_id, kategorie_id),
+    FOREIGN KEY (tmp_user_id) REFERENCES tmp_user(id) ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+-- the removed sessions and the hashed keys of the tmp users can't be restored
+UPDATE tmp_user SET api_key = substr(api_key, 7) WHERE api_key LIKE 'plain:%';
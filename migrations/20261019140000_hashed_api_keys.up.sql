@@ -0,0 +1,5 @@
+-- api keys are only stored as sha256 hashes. sqlite can't hash, so the plain keys of the tmp
+-- users are marked and replaced by their hash on the next request of the tmp user, see
+-- `auth::get_user`. The tmp users keep their stations. Admins have to login again.
+UPDATE tmp_user SET api_key = 'plain:' || api_key;
+DELETE FROM user_session;
@@ -0,0 +1,2 @@
+-- the admin that vouched for a tmp user
+ALTER TABLE tmp_user ADD COLUMN vouched_by TEXT;
@@ -0,0 +1,2 @@
+DROP TABLE join_code_station;
+DROP TABLE join_code;
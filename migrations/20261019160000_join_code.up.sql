@@ -0,0 +1,20 @@
+-- single use codes a helper can redeem to join an event without being vouched for
+CREATE TABLE join_code (
+    code VARCHAR(16) PRIMARY KEY NOT NULL,
+    event_id VARCHAR(10) NOT NULL,
+    role VARCHAR(64) NOT NULL DEFAULT 'helper',
+    created_by TEXT,
+    time_of_creation INT NOT NULL,
+    expires INT NOT NULL,
+    used boolean NOT NULL DEFAULT false,
+    FOREIGN KEY (event_id) REFERENCES event(id) ON DELETE CASCADE,
+    FOREIGN KEY (role) REFERENCES role(name)
+);
+
+-- the stations the tmp user gets, no rows means all
+CREATE TABLE join_code_station (
+    code VARCHAR(16) NOT NULL,
+    kategorie_id INT NOT NULL,
+    PRIMARY KEY (code, kategorie_id),
+    FOREIGN KEY (code) REFERENCES join_code(code) ON DELETE CASCADE
+);
@@ -0,0 +1,22 @@
+-- who did what and when. Rows can only be added, never changed or removed
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    time INT NOT NULL,
+    actor TEXT NOT NULL,
+    action TEXT NOT NULL,
+    event_id VARCHAR(10),
+    target TEXT,
+    before TEXT,
+    after TEXT
+);
+CREATE INDEX audit_log_event ON audit_log(event_id, target);
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'the audit log is append only');
+END;
+
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'the audit log is append only');
+END;
@@ -0,0 +1 @@
+ALTER TABLE tmp_user DROP COLUMN ip;
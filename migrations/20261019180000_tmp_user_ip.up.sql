@@ -0,0 +1,2 @@
+-- the ip that requested a tmp user, the pending requests are limited per ip
+ALTER TABLE tmp_user ADD COLUMN ip TEXT;
@@ -0,0 +1,86 @@
+//! The audit log
+//!
+//! Every action that changes data or is relevant for security is written to the `audit_log` table
+//! of the auth db: who (`actor`) did what (`action`) in which event to what (`target`), and how the
+//! value was before and after. The table is append only, the database rejects changes to it.
+//!
+//! The actor is the username of an admin or the id of a tmp user, like in `versuch.aufsichtId`.
+//! Failed logins are logged as `login_failed` with the ip as actor and the username as target.
+use sqlx::SqlitePool;
+use actix_web::HttpResponse;
+use serde::Serialize;
+use crate::InternalServerf;
+use crate::auth::now;
+use log::error;
+
+/// a action that is added to the audit log
+#[derive(Debug, Default)]
+pub struct AuditAction<'a> {
+    pub actor: &'a str,
+    pub action: &'a str,
+    pub event_id: Option<&'a str>,
+    pub target: Option<String>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub time: i64,
+    pub actor: String,
+    pub action: String,
+    pub event_id: Option<String>,
+    pub target: Option<String>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/**
+ * Adds a action to the audit log.
+ *
+ * This is called after the action is saved, often in a other database, so a failure is only
+ * logged. Returning an error would report a saved change as failed and retries would repeat it.
+ */
+pub async fn record(db: &SqlitePool, action: AuditAction<'_>) {
+    let current_timestamp = now();
+
+    if let Err(e) = sqlx::query!(r#"
+        INSERT INTO audit_log(time, actor, action, event_id, target, before, after) VALUES (?,?,?,?,?,?,?)
+    "#, current_timestamp, action.actor, action.action, action.event_id, action.target, action.before, action.after)
+        .execute(db).await {
+            error!("{} by {} could not be written to the audit log ({})", action.action, action.actor, e);
+    }
+}
+
+/**
+ * The audit log, oldest first.
+ *
+ * With `event_id` only the entries of that event are returned, without it all entries, also
+ * those without an event like logins and role changes. With `target` only the entries about one
+ * thing are returned, e.g. `versuch:12` or `tmp_user:AB12CD`.
+ */
+pub async fn get_audit_log(db: &SqlitePool, event_id: Option<String>, target: Option<String>) -> Result<Vec<AuditEntry>, HttpResponse> {
+    match sqlx::query_as!(AuditEntry, r#"
+        SELECT id, time, actor, action, event_id, target, before, after FROM audit_log
+        WHERE (? IS NULL OR event_id = ?) AND (? IS NULL OR target = ?)
+        ORDER BY id
+    "#, event_id, event_id, target, target).fetch_all(db).await {
+        Ok(r) => Ok(r),
+        Err(e) => Err(InternalServerf!("Error while fetching the audit log ({})", e))
+    }
+}
+
+/// the entries of the audit log as csv, e.g. for a download
+pub fn audit_log_csv(entries: &[AuditEntry]) -> Result<String, HttpResponse> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for entry in entries {
+        if let Err(e) = writer.serialize(entry) {
+            return Err(InternalServerf!("Error while writing the audit log as csv ({})", e));
+        }
+    }
+    match writer.into_inner() {
+        Ok(b) => Ok(String::from_utf8_lossy(&b).to_string()),
+        Err(e) => Err(InternalServerf!("Error while writing the audit log as csv ({})", e))
+    }
+}
@@ -0,0 +1,161 @@
+//! Join codes for station helpers
+//!
+//! Instead of typing the name of a tmp user into the admin's screen, an admin can create a join
+//! code for an event. The code (or the QR code made from `qr_payload`) is redeemed once by the
+//! helper's device, which then gets a tmp user that is already vouched for.
+use sqlx::SqlitePool;
+use actix_web::HttpResponse;
+use serde::Serialize;
+use crate::{InternalServerf, NotFound, BadRequest, Forbiddenf};
+use crate::config::Config;
+use crate::audit::{self, AuditAction};
+use super::{AuthUser, ID_TRIES, gen_base32, gen_api_key, hash_api_key, get_event, now};
+use super::limit::RateLimiter;
+use super::roles::{ADMIN_ROLE, check_role_exists};
+
+const CODE_LENGTH: usize = 8;
+
+#[derive(Debug, Serialize)]
+pub struct JoinCode {
+    pub code: String,
+    pub event_id: String,
+    /// the string to put into a QR code
+    pub qr_payload: String,
+    pub expires: i64,
+}
+
+/**
+ * Creates a single use join code for an event that is valid for `join_code_lifetime` seconds.
+ *
+ * The tmp user that redeems it gets the `role` (`helper` if none is given) and may only record
+ * attempts for `stations`, or for all kategorien if it is empty. `created_by` is the username of
+ * the admin, it is recorded as the one who vouched. A code can't give the `admin` role, anybody who
+ * sees the QR code could redeem it.
+ */
+pub async fn create_join_code(db: &SqlitePool, config: &Config, event_id: String, created_by: String, role: Option<String>, stations: Vec<i64>) -> Result<JoinCode, HttpResponse> {
+    get_event(event_id.clone(), db).await?;
+    let role = role.unwrap_or("helper".to_string());
+    if role == ADMIN_ROLE {
+        return Err(Forbiddenf!("A join code can't give the role {}", ADMIN_ROLE));
+    }
+    check_role_exists(db, &role).await?;
+
+    let current_timestamp = now();
+    let expires = current_timestamp + config.join_code_lifetime;
+
+    let mut tx = match db.begin().await {
+        Ok(t) => t,
+        Err(e) => return Err(InternalServerf!("Error while starting a transaction ({})", e))
+    };
+    // a failed insert doesn't end the transaction, so on a collision the next code can be tried
+    let mut tries = 1;
+    let code = loop {
+        let code = gen_base32(CODE_LENGTH);
+        match sqlx::query!(r#"
+            INSERT INTO join_code(code, event_id, role, created_by, time_of_creation, expires) VALUES (?,?,?,?,?,?)
+        "#, code, event_id, role, created_by, current_timestamp, expires).execute(&mut *tx).await {
+            Ok(_) => break code,
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() && tries < ID_TRIES => tries += 1,
+            Err(e) => return Err(InternalServerf!("Error while inserting the join code ({})", e))
+        }
+    };
+    for kategorie_id in stations {
+        if let Err(e) = sqlx::query!("INSERT OR IGNORE INTO join_code_station(code, kategorie_id) VALUES (?,?)", code, kategorie_id)
+            .execute(&mut *tx).await {
+                return Err(InternalServerf!("Error while adding the station {} to the join code ({})", kategorie_id, e));
+        }
+    }
+    if let Err(e) = tx.commit().await {
+        return Err(InternalServerf!("Error while saving the join code ({})", e));
+    }
+
+    // the code itself is not logged, it can still be redeemed
+    audit::record(db, AuditAction {
+        actor: &created_by,
+        action: "create_join_code",
+        event_id: Some(&event_id),
+        after: Some(format!("role {}", role)),
+        ..Default::default()
+    }).await;
+
+    let qr_payload = format!("emotion://join/{}/{}", event_id, code);
+    Ok(JoinCode { code, event_id, qr_payload, expires })
+}
+
+/**
+ * Redeems a join code and creates a tmp user that is vouched for the event of the code.
+ *
+ * A code can only be redeemed once, even if two devices try at the same time. `ip` is the key of
+ * the client (see `limit::client_ip`), invalid codes count as failures of it like unknown api keys,
+ * so the codes can't be guessed.
+ */
+pub async fn redeem_join_code(db: &SqlitePool, limiter: &RateLimiter, ip: &str, code: String) -> Result<AuthUser, HttpResponse> {
+    limiter.check(ip)?;
+    let code = code.trim().to_uppercase();
+    if code.len() != CODE_LENGTH {
+        limiter.failure(ip);
+        return Err(BadRequest!("The join code is not valid"));
+    }
+    let current_timestamp = now();
+
+    let mut tx = match db.begin().await {
+        Ok(t) => t,
+        Err(e) => return Err(InternalServerf!("Error while starting a transaction ({})", e))
+    };
+
+    // marking it as used and reading it in one statement makes sure only one can redeem it
+    let join_code = match sqlx::query!(r#"
+        UPDATE join_code SET used = true WHERE code = ? AND used = false AND expires >= ?
+        RETURNING event_id, role, created_by
+    "#, code, current_timestamp).fetch_optional(&mut *tx).await {
+        Ok(Some(j)) => j,
+        Ok(None) => {
+            limiter.failure(ip);
+            return Err(NotFound!("The join code was not found, already used or expired"))
+        },
+        Err(e) => return Err(InternalServerf!("Error while redeeming the join code ({})", e))
+    };
+
+    let key = gen_api_key();
+    let key_hash = hash_api_key(&key);
+    let mut tries = 1;
+    let id = loop {
+        let id = gen_base32(6);
+        match sqlx::query!(r#"
+            INSERT INTO tmp_user(id, api_key, vouched, time_of_creation, time_of_activation, last_refresh, event_id, role, vouched_by)
+            VALUES (?,?,True,?,?,?,?,?,?)
+        "#, id, key_hash, current_timestamp, current_timestamp, current_timestamp, join_code.event_id, join_code.role, join_code.created_by)
+            .execute(&mut *tx).await {
+                Ok(_) => break id,
+                Err(sqlx::Error::Database(e)) if e.is_unique_violation() && tries < ID_TRIES => tries += 1,
+                Err(e) => return Err(InternalServerf!("Error while creating the tmp user ({})", e))
+        }
+    };
+    if let Err(e) = sqlx::query!(r#"
+        INSERT INTO tmp_user_station(tmp_user_id, kategorie_id) SELECT ?, kategorie_id FROM join_code_station WHERE code = ?
+    "#, id, code).execute(&mut *tx).await {
+        return Err(InternalServerf!("Error while adding the stations of the tmp user ({})", e));
+    }
+
+    if let Err(e) = tx.commit().await {
+        return Err(InternalServerf!("Error while saving the tmp user ({})", e));
+    }
+    audit::record(db, AuditAction {
+        actor: &id,
+        action: "redeem_join_code",
+        event_id: Some(&join_code.event_id),
+        target: Some(format!("tmp_user:{}", id)),
+        after: Some(format!("role {}", join_code.role)),
+        ..Default::default()
+    }).await;
+    Ok(AuthUser::TmpUser { id, api_key: key, event_id: join_code.event_id })
+}
+
+/// removes the join codes that are used or expired. Returns how many where removed.
+pub async fn remove_old_join_codes(db: &SqlitePool) -> Result<u64, HttpResponse> {
+    let current_timestamp = now();
+    match sqlx::query!("DELETE FROM join_code WHERE used = true OR expires < ?", current_timestamp).execute(db).await {
+        Ok(r) => Ok(r.rows_affected()),
+        Err(e) => Err(InternalServerf!("Error while removing the old join codes ({})", e))
+    }
+}
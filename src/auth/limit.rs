@@ -0,0 +1,214 @@
+//! Rate limiting and lockout
+//!
+//! The limiter lives in memory, so it starts empty when the server restarts. Clients are counted
+//! by a string key, e.g. `key:<hash>`, `ip:<ip>` or `login:<ip>:<username>`. There are three
+//! limits:
+//! + `hit` counts the requests of a known api key and rejects them once there where more than
+//!   `rate_limit_requests` in the `rate_limit_window`
+//! + `failure` counts unknown api keys per ip. After `max_failed_logins` of them in the
+//!   `rate_limit_window` the ip is locked out for `lockout_time` seconds, `check` and `hit`
+//!   reject it in that time.
+//! + `backoff` counts failed logins. Every failure doubles the time until the next try, up to
+//!   `lockout_time` seconds, so a typo costs a second and guessing gets slow.
+//!
+//! Unknown keys have to be counted per ip, every guess is a new key. Devices only send unknown
+//! keys after their session expired, so a school behind one address doesn't reach the limit by
+//! normal use. Logins are counted per ip and username, so somebody else can't lock an admin out.
+//! The auth macros expect a `RateLimiter` in `data.limiter`.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use actix_web::{HttpRequest, HttpResponse};
+use crate::{TooManyRequests, TooManyRequestsf};
+use crate::config::Config;
+
+struct Entry {
+    window_start: Instant,
+    requests: u32,
+    failures: u32,
+    locked_until: Option<Instant>,
+    /// failed logins, unlike `failures` they are not forgotten at the end of the window
+    backoffs: u32,
+    last_backoff: Option<Instant>,
+}
+
+pub struct RateLimiter {
+    max_requests: u32,
+    window: Duration,
+    max_failures: u32,
+    lockout: Duration,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &Config) -> Self {
+        RateLimiter {
+            max_requests: config.rate_limit_requests,
+            window: Duration::from_secs(config.rate_limit_window),
+            max_failures: config.max_failed_logins,
+            lockout: Duration::from_secs(config.lockout_time),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// rejects the key if it is locked out
+    pub fn check(&self, key: &str) -> Result<(), HttpResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get_mut(key) {
+            Some(e) => self.check_entry(e),
+            None => Ok(())
+        }
+    }
+
+    /// counts a request of the key and rejects it if it is locked out or made too many requests
+    pub fn hit(&self, key: &str) -> Result<(), HttpResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(key.to_string()).or_insert_with(new_entry);
+        self.check_entry(entry)?;
+        entry.requests += 1;
+        if entry.requests > self.max_requests {
+            return Err(TooManyRequests!("Too many requests, please wait a moment"));
+        }
+        Ok(())
+    }
+
+    /// counts a unknown api key of a ip, too many of them lock the ip out
+    pub fn failure(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(key.to_string()).or_insert_with(new_entry);
+        self.reset_window(entry);
+        entry.failures += 1;
+        if entry.failures >= self.max_failures {
+            entry.locked_until = Some(Instant::now() + self.lockout);
+        }
+    }
+
+    /// counts a failed login, the key has to wait twice as long as after the last one
+    pub fn backoff(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(key.to_string()).or_insert_with(new_entry);
+        let now = Instant::now();
+        // a failure long ago doesn't count anymore
+        if entry.last_backoff.map(|l| now.duration_since(l) >= self.lockout).unwrap_or(false) {
+            entry.backoffs = 0;
+        }
+        entry.backoffs += 1;
+        entry.last_backoff = Some(now);
+        let delay = Duration::from_secs(1 << (entry.backoffs - 1).min(30)).min(self.lockout);
+        entry.locked_until = Some(now + delay);
+    }
+
+    /// forgets the failures of a key after a successful login
+    pub fn success(&self, key: &str) {
+        if let Some(e) = self.entries.lock().unwrap().get_mut(key) {
+            e.failures = 0;
+            e.backoffs = 0;
+            e.last_backoff = None;
+        }
+    }
+
+    /**
+     * Removes the keys that are neither locked out, in the current window nor had a failed login
+     * in the last `lockout_time` seconds.
+     *
+     * This should be called periodically by the server. Returns the number of removed keys.
+     */
+    pub fn cleanup(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        let now = Instant::now();
+        entries.retain(|_, e| {
+            e.locked_until.map(|l| l > now).unwrap_or(false)
+                || now.duration_since(e.window_start) < self.window
+                || e.last_backoff.map(|l| now.duration_since(l) < self.lockout).unwrap_or(false)
+        });
+        before - entries.len()
+    }
+
+    fn check_entry(&self, entry: &mut Entry) -> Result<(), HttpResponse> {
+        if let Some(l) = entry.locked_until {
+            let now = Instant::now();
+            if l > now {
+                return Err(TooManyRequestsf!("Too many failed attempts, please try again in {} seconds", (l - now).as_secs() + 1));
+            }
+            entry.locked_until = None;
+            entry.failures = 0;
+        }
+        self.reset_window(entry);
+        Ok(())
+    }
+
+    fn reset_window(&self, entry: &mut Entry) {
+        if entry.window_start.elapsed() >= self.window {
+            entry.window_start = Instant::now();
+            entry.requests = 0;
+            entry.failures = 0;
+        }
+    }
+}
+
+/// the key of the ip a request came from
+pub fn client_ip(req: &HttpRequest) -> String {
+    match req.peer_addr() {
+        Some(a) => format!("ip:{}", a.ip()),
+        None => "ip:unknown".to_string()
+    }
+}
+
+fn new_entry() -> Entry {
+    Entry { window_start: Instant::now(), requests: 0, failures: 0, locked_until: None, backoffs: 0, last_backoff: None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lockout_after_failures() {
+        let config = Config { rate_limit_requests: 3, max_failed_logins: 2, ..Config::default() };
+        let limiter = RateLimiter::new(&config);
+
+        for _ in 0..3 {
+            limiter.hit("key:a").unwrap();
+        }
+        assert!(limiter.hit("key:a").is_err());
+        assert!(limiter.hit("key:b").is_ok());
+
+        limiter.failure("ip:c");
+        limiter.success("ip:c");
+        limiter.failure("ip:c");
+        assert!(limiter.check("ip:c").is_ok());
+        limiter.failure("ip:c");
+        assert!(limiter.check("ip:c").is_err());
+        assert!(limiter.check("ip:d").is_ok());
+        assert_eq!(limiter.cleanup(), 0);
+    }
+
+    #[test]
+    fn backoff_after_failed_logins() {
+        let config = Config { lockout_time: 4, ..Config::default() };
+        let limiter = RateLimiter::new(&config);
+
+        limiter.backoff("login:ip:1:admin");
+        assert!(limiter.check("login:ip:1:admin").is_err());
+        // other ips can still login as the same admin
+        assert!(limiter.check("login:ip:2:admin").is_ok());
+
+        let locked_for = |key: &str| {
+            let entries = limiter.entries.lock().unwrap();
+            entries[key].locked_until.unwrap().duration_since(Instant::now()).as_secs_f64()
+        };
+        limiter.backoff("login:ip:1:admin");
+        assert!(locked_for("login:ip:1:admin") > 1.0);
+        // the delay doesn't grow beyond the lockout time
+        for _ in 0..10 {
+            limiter.backoff("login:ip:1:admin");
+        }
+        assert!(locked_for("login:ip:1:admin") <= 4.0);
+
+        limiter.success("login:ip:1:admin");
+        limiter.entries.lock().unwrap().get_mut("login:ip:1:admin").unwrap().locked_until = None;
+        limiter.backoff("login:ip:1:admin");
+        assert!(locked_for("login:ip:1:admin") <= 1.0);
+    }
+}
@@ -14,13 +14,25 @@
 //! This should enforce that the user is a Admin, as well as a user varible, also it should expose
 //! the event Varible
 
-use crate::{Forbidden,NotFound,InternalServer, BadRequest, Unauthorized};
+pub mod roles;
+pub mod sessions;
+pub mod join_codes;
+pub mod limit;
+
+use crate::{Forbidden,NotFound,InternalServer, BadRequest, Unauthorized, Conflict, InternalServerf, BadRequestf, NotFoundf, TooManyRequests};
+use crate::config::Config;
+use crate::audit::{self, AuditAction};
+use limit::RateLimiter;
 use sqlx::SqlitePool;
 use actix_web::{HttpRequest, HttpResponse};
 use sha256::digest;
+use std::sync::LazyLock;
 use std::time::{SystemTime, UNIX_EPOCH};
 use rand::prelude::*;
 use log::info;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::SaltString;
+use argon2::password_hash::rand_core::OsRng;
 
 /**
  * includes all the information to access the Database
@@ -47,45 +59,78 @@ pub enum RequestUser {
  * This user shall exist and have a valid session
  */
 pub enum AuthUser {
-    Admin{api_key: String},
-    AdminWithEvent{api_key: String, event_id: String},
+    Admin{api_key: String, username: String},
+    AdminWithEvent{api_key: String, username: String, event_id: String},
     TmpUser{id: String, api_key: String, event_id: String},
     NotApprovedTmpUser{id: String, api_key: String}
 }
 
+impl AuthUser {
+    /// the username of a admin or the id of a tmp user
+    pub fn name(&self) -> &str {
+        match self {
+            AuthUser::Admin { username, .. } | AuthUser::AdminWithEvent { username, .. } => username,
+            AuthUser::TmpUser { id, .. } | AuthUser::NotApprovedTmpUser { id, .. } => id
+        }
+    }
+
+    pub fn event_id(&self) -> Option<&str> {
+        match self {
+            AuthUser::AdminWithEvent { event_id, .. } | AuthUser::TmpUser { event_id, .. } => Some(event_id),
+            AuthUser::Admin { .. } | AuthUser::NotApprovedTmpUser { .. } => None
+        }
+    }
+}
+
 /**
  * Get the Userver from the Database. While doing this the validity of the session is checked
+ *
+ * Every known key is rate limited and unknown keys count as failures of the ip they came from,
+ * see `limit`.
+ *
+ * The keys are looked up by their sha256 hash, so the time the database needs to compare them
+ * only tells something about the hash and not about the key. That is why there is no constant
+ * time comparison.
  */
-pub async fn get_user(req: &HttpRequest, db: &SqlitePool) -> Result<AuthUser, HttpResponse> {
+pub async fn get_user(req: &HttpRequest, db: &SqlitePool, config: &Config, limiter: &RateLimiter) -> Result<AuthUser, HttpResponse> {
     let user = req2user(req)?;
+    let ip = limit::client_ip(req);
+    limiter.check(&ip)?;
     // get Current time
     // this is used to check if the Session is valid and update it to the new number
-    let current_timestamp: i64 = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as i64;
+    let current_timestamp = now();
 
     match user {
         RequestUser::TmpUser{api_key} => {
-            // check if the User is approved
-            let validated = sqlx::query!( r#"
-                SELECT id, vouched, last_refresh, event_id from tmp_user WHERE api_key = ?
-            "#, api_key).fetch_one(db).await;
-
-            let user_data = match validated {
-                Ok(r) => r,
-                Err(sqlx::Error::RowNotFound) => return Err(NotFound!("The api_key was not found")),
-                Err(_) => return  Err(InternalServer!("Error while fetching user db"))
+            let key_hash = hash_api_key(&api_key);
+            let mut upgraded = false;
+            let user_data = loop {
+                // check if the User is approved
+                let validated = sqlx::query!( r#"
+                    SELECT id, vouched, role, time_of_creation, last_refresh, event_id from tmp_user WHERE api_key = ?
+                "#, key_hash).fetch_one(db).await;
+
+                match validated {
+                    Ok(r) => break r,
+                    Err(sqlx::Error::RowNotFound) if !upgraded && upgrade_plain_key(db, &api_key, &key_hash).await? => upgraded = true,
+                    Err(sqlx::Error::RowNotFound) => {
+                        limiter.failure(&ip);
+                        return Err(NotFound!("The api_key was not found"))
+                    },
+                    Err(_) => return  Err(InternalServer!("Error while fetching user db"))
+                }
             };
+            // only keys that exist get an entry in the limiter
+            limiter.hit(&format!("key:{}", key_hash))?;
 
-            if current_timestamp - user_data.last_refresh > 18000 {
-                return Err(Forbidden!("Sorry, key was not refreshed"));
+            if let Some(reason) = sessions::session_expired(config, &user_data.role, false, user_data.vouched, user_data.time_of_creation, user_data.last_refresh, current_timestamp) {
+                return Err(Forbidden!(reason));
             }
 
             // reset last_refresh
             if let Err(e) = sqlx::query!(r#"
                 UPDATE tmp_user SET last_refresh = ? WHERE api_key = ?
-            "#, current_timestamp, api_key)
+            "#, current_timestamp, key_hash)
                 .execute(db)
                 .await {
                     return Err(InternalServer!(format!("There was an Error while Updating the tmp_user ({e})")));
@@ -106,24 +151,38 @@ pub async fn get_user(req: &HttpRequest, db: &SqlitePool) -> Result<AuthUser, Ht
         },
 
         RequestUser::Admin{ ref api_key } | RequestUser::AdminWithEvent { ref api_key ,.. } => {
+            let key_hash = hash_api_key(api_key);
             let user_data_opt= sqlx::query!( r#"
-                SELECT last_refresh from user_session WHERE api_key = ?
-            "#, api_key).fetch_one(db).await;
+                SELECT user_session.time_of_creation, last_refresh, user_session.username, role as "role?" from user_session
+                LEFT JOIN admin_user ON admin_user.username = user_session.username
+                WHERE api_key = ?
+            "#, key_hash).fetch_one(db).await;
 
             let user_data = match user_data_opt {
                 Ok(r) => r,
-                Err(sqlx::Error::RowNotFound) => return Err(NotFound!("The admin_api_key was not found")),
+                Err(sqlx::Error::RowNotFound) => {
+                    limiter.failure(&ip);
+                    return Err(NotFound!("The admin_api_key was not found"))
+                },
                 Err(_) => return  Err(InternalServer!("Error while fetching user db"))
             };
+            limiter.hit(&format!("key:{}", key_hash))?;
 
-            if current_timestamp - user_data.last_refresh > 36000 {
-                return Err(Forbidden!("Sorry, key was not refreshed"));
+            let role = user_data.role.unwrap_or_default();
+            if let Some(reason) = sessions::session_expired(config, &role, true, true, user_data.time_of_creation, user_data.last_refresh, current_timestamp) {
+                return Err(Forbidden!(reason));
             }
 
+            // sessions from before the login where introduced don't belong to anyone
+            let username = match user_data.username {
+                Some(u) => u,
+                None => return Err(Forbidden!("This session has no user, please login again"))
+            };
+
             // reset last_refresh
             if let Err(e) = sqlx::query!(r#"
                 UPDATE user_session SET last_refresh = ? WHERE api_key = ?
-            "#, current_timestamp, api_key)
+            "#, current_timestamp, key_hash)
                 .execute(db)
                 .await {
                     return Err(InternalServer!(format!("There was an error reseting the refresh id ({e})")))
@@ -142,11 +201,11 @@ pub async fn get_user(req: &HttpRequest, db: &SqlitePool) -> Result<AuthUser, Ht
                     Err(_) => return  Err(InternalServer!("Error while fetching user db"))
                 };
 
-                return Ok(AuthUser::AdminWithEvent{ api_key, event_id});
+                return Ok(AuthUser::AdminWithEvent{ api_key, username, event_id});
             }
 
             // Now the only thing that is left is a AdminUser without event
-            return Ok(AuthUser::Admin{ api_key: api_key.to_string() });
+            Ok(AuthUser::Admin{ api_key: api_key.to_string(), username })
         }
     }
 }
@@ -206,30 +265,127 @@ pub async fn get_event(event_id: String, db: &SqlitePool) -> Result<Event,HttpRe
 
 /**
  * This function can be used to vouch for a tmp user
+ *
+ * With `stations` the user can only record attempts for these kategorien. If it is empty the
+ * user may record attempts for every kategorie of the event. `vouched_by` is the username of the
+ * admin that vouched. Requests that are older than `pending_tmp_user_lifetime` can't be vouched for.
  */
-pub async fn vouch_tmp_user(db: &SqlitePool, event_id: String, tmp_user_id: String) -> Result<(), HttpResponse> {
-    let current_timestamp: i64 = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as i64;
-
+pub async fn vouch_tmp_user(db: &SqlitePool, config: &Config, event_id: String, tmp_user_id: String, vouched_by: String, stations: Vec<i64>) -> Result<(), HttpResponse> {
+    let current_timestamp = now();
+    let oldest_request = current_timestamp - config.pending_tmp_user_lifetime;
+
+    // the vouching and the stations are saved together, so a helper never records without its stations
+    let mut tx = match db.begin().await {
+        Ok(t) => t,
+        Err(e) => return Err(InternalServerf!("Error while starting a transaction ({})", e))
+    };
     let rows_affected = match sqlx::query!(r#"
-        UPDATE tmp_user SET vouched = True, time_of_creation = ?, event_id = ? WHERE id = ?
-    "#, current_timestamp, event_id, tmp_user_id)
-        .execute(db).await {
+        UPDATE tmp_user SET vouched = True, time_of_activation = ?, vouched_by = ?, event_id = ?
+        WHERE id = ? AND (vouched OR time_of_creation >= ?)
+    "#, current_timestamp, vouched_by, event_id, tmp_user_id, oldest_request)
+        .execute(&mut *tx).await {
             Ok(r) => r.rows_affected(),
             Err(e) => return Err(InternalServer!(format!("There was an error while vouching for user ({e})")))
         };
     
     if rows_affected == 0 {
-        return Err(NotFound!("The tmp_user was not found"))
+        return Err(NotFound!("The tmp_user was not found or the request expired"))
     }
 
     if rows_affected > 1 {
         return Err(InternalServer!("There are two users by that id. For both has been vouched"))
     }
 
-    return Ok(())
+    let after = format!("stations {:?}", stations);
+    replace_stations(&mut tx, &tmp_user_id, &stations).await?;
+    if let Err(e) = tx.commit().await {
+        return Err(InternalServerf!("Error while vouching for {} ({})", tmp_user_id, e));
+    }
+    audit::record(db, AuditAction {
+        actor: &vouched_by,
+        action: "vouch_tmp_user",
+        event_id: Some(&event_id),
+        target: Some(format!("tmp_user:{}", tmp_user_id)),
+        after: Some(after),
+        ..Default::default()
+    }).await;
+    Ok(())
+}
+
+/// the kategorien a tmp user may record attempts for. Empty means all of them.
+pub async fn get_stations(db: &SqlitePool, tmp_user_id: &str) -> Result<Vec<i64>, HttpResponse> {
+    match sqlx::query!("SELECT kategorie_id FROM tmp_user_station WHERE tmp_user_id = ?", tmp_user_id)
+        .fetch_all(db).await {
+            Ok(r) => Ok(r.into_iter().map(|s| s.kategorie_id).collect()),
+            Err(e) => Err(InternalServerf!("Error while fetching the stations of {} ({})", tmp_user_id, e))
+    }
+}
+
+/// assigns a tmp user to the given kategorien, replacing the old assignment. `actor` is written to the audit log.
+pub async fn set_stations(db: &SqlitePool, actor: &str, tmp_user_id: String, stations: Vec<i64>) -> Result<(), HttpResponse> {
+    let event_id = match sqlx::query!("SELECT event_id FROM tmp_user WHERE id = ?", tmp_user_id).fetch_optional(db).await {
+        Ok(Some(r)) => r.event_id,
+        Ok(None) => return Err(NotFoundf!("The tmp_user {} was not found", tmp_user_id)),
+        Err(e) => return Err(InternalServerf!("Error while fetching the tmp_user {} ({})", tmp_user_id, e))
+    };
+    let mut tx = match db.begin().await {
+        Ok(t) => t,
+        Err(e) => return Err(InternalServerf!("Error while starting a transaction ({})", e))
+    };
+    replace_stations(&mut tx, &tmp_user_id, &stations).await?;
+    if let Err(e) = tx.commit().await {
+        return Err(InternalServerf!("Error while saving the stations of {} ({})", tmp_user_id, e));
+    }
+    audit::record(db, AuditAction {
+        actor,
+        action: "set_stations",
+        event_id: event_id.as_deref(),
+        target: Some(format!("tmp_user:{}", tmp_user_id)),
+        after: Some(format!("stations {:?}", stations)),
+        ..Default::default()
+    }).await;
+    Ok(())
+}
+
+/// replaces the stations of a tmp user in a transaction
+async fn replace_stations(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>, tmp_user_id: &str, stations: &[i64]) -> Result<(), HttpResponse> {
+    if let Err(e) = sqlx::query!("DELETE FROM tmp_user_station WHERE tmp_user_id = ?", tmp_user_id).execute(&mut **tx).await {
+        return Err(InternalServerf!("Error while removing the stations of {} ({})", tmp_user_id, e));
+    }
+    for kategorie_id in stations {
+        if let Err(e) = sqlx::query!("INSERT OR IGNORE INTO tmp_user_station(tmp_user_id, kategorie_id) VALUES (?,?)", tmp_user_id, kategorie_id)
+            .execute(&mut **tx).await {
+                return Err(InternalServerf!("Error while assigning {} to the kategorie {} ({})", tmp_user_id, kategorie_id, e));
+        }
+    }
+    Ok(())
+}
+
+/// The migrations of the auth database for `#[sqlx::test]`. The dosb, bjs and merged
+/// migrations set up the databases of the evaluations and can't be applied on top of it.
+#[cfg(test)]
+pub(crate) static MIGRATOR: LazyLock<sqlx::migrate::Migrator> = LazyLock::new(|| {
+    let mut migrator = sqlx::migrate!("./migrations");
+    migrator.migrations = migrator.migrations.iter()
+        .filter(|m| !matches!(&*m.description, "dosb2025" | "bjs2025" | "merged"))
+        .cloned()
+        .collect();
+    migrator
+});
+
+/// the characters used for names and passwords, without 0 and O to avoid confusion
+const BASE32: [char; 32] = [
+    'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'P', 'Q', 'R', 'S',
+    'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '1', '2', '3', '4', '5', '6', '7',
+];
+
+/// how often a new random id is tried when the last one was already taken
+pub(crate) const ID_TRIES: u32 = 5;
+
+/// a random string of `len` BASE32 characters, used for names and codes that are typed by hand
+pub(crate) fn gen_base32(len: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..len).map(|_| BASE32[rng.gen_range(0..BASE32.len())]).collect()
 }
 
 /// generate a random hash
@@ -241,62 +397,546 @@ fn gen_api_key() -> String {
     digest(tmp)
 }
 
-/// create a tmp user
-pub async fn create_tmp_user(db: &SqlitePool) -> Result<AuthUser, HttpResponse> {
-    let current_timestamp: i64 = SystemTime::now()
+/// the current unix time in seconds
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
-        .as_secs() as i64;
+        .as_secs() as i64
+}
 
-    //gen Name
-    let mut rng = rand::thread_rng();
-    let mut name = "".to_string();
-    let base32 = [
-        'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'P', 'Q', 'R', 'S',
-        'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '1', '2', '3', '4', '5', '6', '7',
-    ];
-    for _ in 1..7 {
-        let i = (rng.gen::<f64>() * 32.0) as usize;
-        let c = base32.get(i).unwrap() as &char;
-        name.push(c.to_owned());
+/// only the hash of a api key is stored, so the auth db can't be used to impersonate anyone
+pub(crate) fn hash_api_key(api_key: &str) -> String {
+    digest(api_key)
+}
+
+/**
+ * Replaces the plain key of a tmp user from before the keys were hashed with its hash.
+ *
+ * The migration to the hashes marks the plain keys with `plain:`, so a hash that is sent as a key
+ * can't match a key that is already hashed. Returns true if there was such a tmp user.
+ */
+async fn upgrade_plain_key(db: &SqlitePool, api_key: &str, key_hash: &str) -> Result<bool, HttpResponse> {
+    let plain = format!("plain:{}", api_key);
+    match sqlx::query!("UPDATE tmp_user SET api_key = ? WHERE api_key = ?", key_hash, plain).execute(db).await {
+        Ok(r) => Ok(r.rows_affected() == 1),
+        Err(e) => Err(InternalServer!(format!("Error while upgrading the api_key of a tmp_user ({e})")))
     }
+}
+
+/**
+ * create a tmp user
+ *
+ * `ip` is the key of the client, see `limit::client_ip`. At most `max_pending_tmp_users` of one ip
+ * may wait to be vouched for at the same time, so one client can't fill the table and keep the
+ * helpers of the other clients out.
+ */
+pub async fn create_tmp_user(db: &SqlitePool, config: &Config, ip: &str) -> Result<AuthUser, HttpResponse> {
+    let current_timestamp = now();
+
+    let oldest_request = current_timestamp - config.pending_tmp_user_lifetime;
 
     // gen key
     let key = gen_api_key();
+    let key_hash = hash_api_key(&key);
+
+    //gen Name, the names are short so they can collide
+    let mut tries = 1;
+    let name = loop {
+        let name = gen_base32(6);
+        // the pending users are counted in the insert, so parallel requests can't pass the limit
+        match sqlx::query!(r#"
+            INSERT INTO tmp_user(id, api_key, vouched, time_of_creation, last_refresh, ip)
+            SELECT ?,?,False,?,?,?
+            WHERE (SELECT COUNT(*) FROM tmp_user WHERE vouched = false AND time_of_creation >= ? AND ip = ?) < ?
+            "#,
+            name,
+            key_hash,
+            current_timestamp,
+            current_timestamp,
+            ip,
+            oldest_request,
+            ip,
+            config.max_pending_tmp_users)
+            .execute(db)
+            .await {
+                Ok(r) if r.rows_affected() == 0 => return Err(TooManyRequests!("Too many users of this network are waiting to be vouched for, please try again later")),
+                Ok(_) => break name,
+                Err(sqlx::Error::Database(e)) if e.is_unique_violation() && tries < ID_TRIES => tries += 1,
+                Err(_) => return Err(InternalServer!("There was an error inserting the user into the DB"))
+        }
+    };
 
-    if sqlx::query!(r#"
-        INSERT INTO tmp_user(id, api_key, vouched, time_of_creation, last_refresh) VALUES (?,?,False,?,?)
-        "#,
-        name,
-        key,
-        current_timestamp,
-        current_timestamp)
-        .execute(db)
-        .await.is_err() {
-            return Err(InternalServer!("There was an error inserting the user into the DB"))
+    return Ok(AuthUser::NotApprovedTmpUser {id: name, api_key: key});
+}
+
+/**
+ * Login of a admin. On success a new admin session is created.
+ *
+ * `ip` is the key of the client, see `limit::client_ip`. Every wrong password doubles the time
+ * until the username can be tried again from that ip.
+ */
+pub async fn login(db: &SqlitePool, limiter: &RateLimiter, ip: &str, username: String, password: String) -> Result<AuthUser, HttpResponse> {
+    let limit_key = format!("login:{}:{}", ip, username);
+    limiter.check(&limit_key)?;
+    let user = match sqlx::query!("SELECT password_hash FROM admin_user WHERE username = ?", username)
+        .fetch_optional(db).await {
+            Ok(u) => u,
+            Err(e) => return Err(InternalServerf!("Error while fetching the admin {} ({})", username, e))
+    };
+
+    // don't tell if the user or the password was wrong, a unknown user is checked against a
+    // dummy hash so it takes as long as a wrong password
+    let valid = match user {
+        Some(u) => verify_password(&password, &u.password_hash)?,
+        None => {
+            let _ = verify_password(&password, &DUMMY_PASSWORD_HASH);
+            false
+        }
+    };
+    if !valid {
+        limiter.backoff(&limit_key);
+        audit::record(db, AuditAction { actor: ip, action: "login_failed", target: Some(format!("admin:{}", username)), ..Default::default() }).await;
+        return Err(Unauthorized!("Wrong username or password"));
     }
+    limiter.success(&limit_key);
 
-    return Ok(AuthUser::NotApprovedTmpUser {id: name, api_key: key});
+    info!("admin {} logged in", username);
+    let user = create_session(db, username).await?;
+    audit::record(db, AuditAction { actor: user.name(), action: "login", ..Default::default() }).await;
+    Ok(user)
+}
+
+/**
+ * Creates the first admin. This only works as long as there is no admin at all, after that new
+ * admins have to be created by an existing one with `create_admin`.
+ */
+pub async fn bootstrap_admin(db: &SqlitePool, username: String, password: String) -> Result<(), HttpResponse> {
+    let password_hash = new_admin_password_hash(&username, &password)?;
+    let current_timestamp = now();
+
+    // checked in the insert, so two bootstraps at the same time can't both succeed
+    match sqlx::query!(r#"
+        INSERT INTO admin_user(username, password_hash, time_of_creation, time_of_password_change)
+        SELECT ?,?,?,? WHERE NOT EXISTS (SELECT 1 FROM admin_user)
+    "#, username, password_hash, current_timestamp, current_timestamp)
+        .execute(db).await {
+            Ok(r) if r.rows_affected() == 0 => return Err(Conflict!("There already is an admin")),
+            Ok(_) => (),
+            Err(e) => return Err(InternalServerf!("Error while inserting the admin ({})", e))
+    }
+    audit::record(db, AuditAction { actor: &username, action: "create_admin", target: Some(format!("admin:{}", username)), ..Default::default() }).await;
+    Ok(())
+}
+
+/// creates a new admin
+pub async fn create_admin(db: &SqlitePool, actor: &str, username: String, password: String) -> Result<(), HttpResponse> {
+    let password_hash = new_admin_password_hash(&username, &password)?;
+    let current_timestamp = now();
+
+    match sqlx::query!(r#"
+        INSERT INTO admin_user(username, password_hash, time_of_creation, time_of_password_change) VALUES (?,?,?,?)
+    "#, username, password_hash, current_timestamp, current_timestamp)
+        .execute(db).await {
+            Ok(_) => (),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => return Err(Conflict!("There already is an admin with this name")),
+            Err(e) => return Err(InternalServerf!("Error while inserting the admin ({})", e))
+    }
+    audit::record(db, AuditAction { actor, action: "create_admin", target: Some(format!("admin:{}", username)), ..Default::default() }).await;
+    Ok(())
+}
+
+/// checks the username of a new admin and hashes the password
+fn new_admin_password_hash(username: &str, password: &str) -> Result<String, HttpResponse> {
+    if username.trim().is_empty() || username.len() > 64 {
+        return Err(BadRequest!("The username has to be between 1 and 64 characters long"));
+    }
+    hash_password(password)
+}
+
+/**
+ * Changes the password of an admin. All sessions of the admin are ended, so they have to login
+ * again with the new password.
+ *
+ * A wrong old password makes the ip wait like a failed `login`.
+ */
+pub async fn change_password(db: &SqlitePool, limiter: &RateLimiter, ip: &str, username: String, old_password: String, new_password: String) -> Result<(), HttpResponse> {
+    let limit_key = format!("login:{}:{}", ip, username);
+    limiter.check(&limit_key)?;
+    let user = match sqlx::query!("SELECT password_hash FROM admin_user WHERE username = ?", username)
+        .fetch_optional(db).await {
+            Ok(u) => u,
+            Err(e) => return Err(InternalServerf!("Error while fetching the admin {} ({})", username, e))
+    };
+    match user {
+        Some(u) if verify_password(&old_password, &u.password_hash)? => limiter.success(&limit_key),
+        _ => {
+            limiter.backoff(&limit_key);
+            return Err(Unauthorized!("Wrong username or password"))
+        }
+    }
+
+    set_password(db, &username, &new_password).await?;
+    audit::record(db, AuditAction { actor: &username, action: "change_password", target: Some(format!("admin:{}", username)), ..Default::default() }).await;
+    Ok(())
+}
+
+/**
+ * Resets the password of an admin to a random one, that is returned. Like with
+ * `change_password` all sessions of the admin are ended.
+ */
+pub async fn reset_password(db: &SqlitePool, actor: &str, username: String) -> Result<String, HttpResponse> {
+    let password = gen_base32(16);
+    set_password(db, &username, &password).await?;
+    audit::record(db, AuditAction { actor, action: "reset_password", target: Some(format!("admin:{}", username)), ..Default::default() }).await;
+    Ok(password)
+}
+
+async fn set_password(db: &SqlitePool, username: &str, password: &str) -> Result<(), HttpResponse> {
+    let password_hash = hash_password(password)?;
+    let current_timestamp = now();
+
+    let rows_affected = match sqlx::query!("UPDATE admin_user SET password_hash = ?, time_of_password_change = ? WHERE username = ?",
+        password_hash, current_timestamp, username)
+        .execute(db).await {
+            Ok(r) => r.rows_affected(),
+            Err(e) => return Err(InternalServerf!("Error while updating the password ({})", e))
+    };
+    if rows_affected == 0 {
+        return Err(NotFoundf!("The admin {} was not found", username));
+    }
+
+    if let Err(e) = sqlx::query!("DELETE FROM user_session WHERE username = ?", username).execute(db).await {
+        return Err(InternalServerf!("Error while ending the sessions of {} ({})", username, e));
+    }
+    Ok(())
+}
+
+fn hash_password(password: &str) -> Result<String, HttpResponse> {
+    if password.chars().count() < 8 {
+        return Err(BadRequestf!("The password has to be at least {} characters long", 8));
+    }
+    let salt = SaltString::generate(&mut OsRng);
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(h) => Ok(h.to_string()),
+        Err(e) => Err(InternalServerf!("Error while hashing the password ({})", e))
+    }
+}
+
+/// the hash of a random password, checked on logins of unknown admins
+static DUMMY_PASSWORD_HASH: LazyLock<String> = LazyLock::new(|| hash_password(&gen_base32(16)).unwrap_or_default());
+
+fn verify_password(password: &str, password_hash: &str) -> Result<bool, HttpResponse> {
+    let parsed = match PasswordHash::new(password_hash) {
+        Ok(h) => h,
+        Err(e) => return Err(InternalServerf!("The stored password hash is broken ({})", e))
+    };
+    Ok(Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
 }
 
 /** 
  * create a new admin session
  */
-pub async fn create_session(db: &SqlitePool) -> Result<AuthUser, HttpResponse> {
-    let current_timestamp: i64 = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as i64;
+async fn create_session(db: &SqlitePool, username: String) -> Result<AuthUser, HttpResponse> {
+    let current_timestamp = now();
 
     let api_key = format!("TEACH_{}", gen_api_key());
+    let key_hash = hash_api_key(&api_key);
 
     if let Err(e) = sqlx::query!(r#"
-        INSERT INTO user_session(api_key, time_of_creation, last_refresh) VALUES (?,?,?)
-    "#, api_key, current_timestamp, current_timestamp)
+        INSERT INTO user_session(api_key, time_of_creation, last_refresh, username) VALUES (?,?,?,?)
+    "#, key_hash, current_timestamp, current_timestamp, username)
         .execute(db)
         .await {
             return Err(InternalServer!(format!("Error while inserting into the database ({})", e)))
     }
 
-    return Ok(AuthUser::Admin {api_key})
+    Ok(AuthUser::Admin {api_key, username})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test(migrator = "crate::auth::MIGRATOR")]
+    async fn login_and_change_password(db: SqlitePool) {
+        let limiter = RateLimiter::new(&Config::default());
+        assert!(bootstrap_admin(&db, "admin".to_string(), "short".to_string()).await.is_err());
+        bootstrap_admin(&db, "admin".to_string(), "correct horse".to_string()).await.unwrap();
+        // there can only be one bootstrap
+        assert!(bootstrap_admin(&db, "other".to_string(), "correct horse".to_string()).await.is_err());
+
+        assert!(login(&db, &limiter, "ip:2", "admin".to_string(), "wrong password".to_string()).await.is_err());
+        assert!(login(&db, &limiter, "ip:1", "nobody".to_string(), "correct horse".to_string()).await.is_err());
+        let failed: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM audit_log WHERE action = 'login_failed'").fetch_one(&db).await.unwrap();
+        assert_eq!(failed.0, 2);
+        match login(&db, &limiter, "ip:1", "admin".to_string(), "correct horse".to_string()).await.unwrap() {
+            AuthUser::Admin { api_key, username } => {
+                assert!(api_key.starts_with("TEACH_"));
+                assert_eq!(username, "admin");
+            },
+            _ => panic!("login has to return a admin")
+        }
+
+        assert!(change_password(&db, &limiter, "ip:4", "admin".to_string(), "wrong password".to_string(), "battery staple".to_string()).await.is_err());
+        // the ip has to wait after a wrong password
+        assert!(change_password(&db, &limiter, "ip:4", "admin".to_string(), "correct horse".to_string(), "battery staple".to_string()).await.is_err());
+        change_password(&db, &limiter, "ip:1", "admin".to_string(), "correct horse".to_string(), "battery staple".to_string()).await.unwrap();
+        assert!(login(&db, &limiter, "ip:3", "admin".to_string(), "correct horse".to_string()).await.is_err());
+        // changing the password ends all sessions
+        let sessions: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM user_session").fetch_one(&db).await.unwrap();
+        assert_eq!(sessions.0, 0);
+
+        let password = reset_password(&db, "admin", "admin".to_string()).await.unwrap();
+        login(&db, &limiter, "ip:1", "admin".to_string(), password).await.unwrap();
+    }
+
+    #[sqlx::test(migrator = "crate::auth::MIGRATOR")]
+    async fn permissions_of_roles(db: SqlitePool) {
+        let limiter = RateLimiter::new(&Config::default());
+        bootstrap_admin(&db, "admin".to_string(), "correct horse".to_string()).await.unwrap();
+        let user = login(&db, &limiter, "ip:1", "admin".to_string(), "correct horse".to_string()).await.unwrap();
+        // the first admin can do everything
+        for p in roles::PERMISSIONS {
+            roles::check_permission(&user, p, &db).await.unwrap();
+        }
+        roles::check_admin(&user, &db).await.unwrap();
+        assert!(roles::check_permission(&user, "fly", &db).await.is_err());
+
+        roles::set_admin_role(&db, "admin", "admin".to_string(), "class_teacher".to_string(), Some("7a".to_string())).await.unwrap();
+        let role = roles::check_permission(&user, "view_results", &db).await.unwrap();
+        assert!(role.can_see_klasse(Some("7a")));
+        assert!(!role.can_see_klasse(Some("7b")));
+        assert!(!role.can_see_klasse(None));
+        assert!(roles::check_permission(&user, "record_attempts", &db).await.is_err());
+        assert!(roles::check_admin(&user, &db).await.is_err());
+
+        assert!(roles::set_admin_role(&db, "admin", "admin".to_string(), "king".to_string(), None).await.is_err());
+        assert!(roles::set_role(&db, "admin", "jury".to_string(), vec!["fly".to_string()]).await.is_err());
+        roles::set_role(&db, "admin", "jury".to_string(), vec!["view_results".to_string()]).await.unwrap();
+        let all = roles::get_roles(&db).await.unwrap();
+        assert_eq!(all.iter().find(|r| r.name == "jury").unwrap().permissions, vec!["view_results".to_string()]);
+
+        // role changes have no event, they are only in the whole log
+        let log = audit::get_audit_log(&db, None, None).await.unwrap();
+        let actions: Vec<&str> = log.iter().map(|l| l.action.as_str()).filter(|a| a.starts_with("set_")).collect();
+        assert_eq!(actions, vec!["set_admin_role", "set_role"]);
+    }
+
+    #[sqlx::test(migrator = "crate::auth::MIGRATOR")]
+    async fn vouch_with_stations(db: SqlitePool) {
+        sqlx::query("INSERT INTO event(id, name) VALUES ('ev', 'Sportfest')").execute(&db).await.unwrap();
+        let id = match create_tmp_user(&db, &Config::default(), "ip:1").await.unwrap() {
+            AuthUser::NotApprovedTmpUser { id, .. } => id,
+            _ => panic!("a new tmp user is not approved")
+        };
+
+        vouch_tmp_user(&db, &Config::default(), "ev".to_string(), id.clone(), "admin".to_string(), vec![4, 6]).await.unwrap();
+        let mut stations = get_stations(&db, &id).await.unwrap();
+        stations.sort();
+        assert_eq!(stations, vec![4, 6]);
+
+        set_stations(&db, "admin", id.clone(), vec![]).await.unwrap();
+        assert!(get_stations(&db, &id).await.unwrap().is_empty());
+        assert!(set_stations(&db, "admin", "nobody".to_string(), vec![]).await.is_err());
+        let log = audit::get_audit_log(&db, Some("ev".to_string()), Some(format!("tmp_user:{}", id))).await.unwrap();
+        let actions: Vec<&str> = log.iter().map(|l| l.action.as_str()).collect();
+        assert_eq!(actions, vec!["vouch_tmp_user", "set_stations"]);
+    }
+
+    #[sqlx::test(migrator = "crate::auth::MIGRATOR")]
+    async fn only_hashes_are_stored(db: SqlitePool) {
+        let key = match create_tmp_user(&db, &Config::default(), "ip:1").await.unwrap() {
+            AuthUser::NotApprovedTmpUser { api_key, .. } => api_key,
+            _ => panic!("a new tmp user is not approved")
+        };
+        let stored: (String,) = sqlx::query_as("SELECT api_key FROM tmp_user").fetch_one(&db).await.unwrap();
+        assert_ne!(stored.0, key);
+        assert_eq!(stored.0, hash_api_key(&key));
+
+        // a key from before the hashes is replaced by its hash, a hash sent as key matches nothing
+        sqlx::query("UPDATE tmp_user SET api_key = 'plain:' || ?").bind(&key).execute(&db).await.unwrap();
+        assert!(!upgrade_plain_key(&db, &hash_api_key(&key), &hash_api_key(&hash_api_key(&key))).await.unwrap());
+        assert!(upgrade_plain_key(&db, &key, &hash_api_key(&key)).await.unwrap());
+        let stored: (String,) = sqlx::query_as("SELECT api_key FROM tmp_user").fetch_one(&db).await.unwrap();
+        assert_eq!(stored.0, hash_api_key(&key));
+    }
+
+    #[sqlx::test(migrator = "crate::auth::MIGRATOR")]
+    async fn list_and_revoke_sessions(db: SqlitePool) {
+        let limiter = RateLimiter::new(&Config::default());
+        let config = Config::default();
+        sqlx::query("INSERT INTO event(id, name) VALUES ('ev', 'Sportfest')").execute(&db).await.unwrap();
+        bootstrap_admin(&db, "admin".to_string(), "correct horse".to_string()).await.unwrap();
+        let admin = login(&db, &limiter, "ip:1", "admin".to_string(), "correct horse".to_string()).await.unwrap();
+        login(&db, &limiter, "ip:1", "admin".to_string(), "correct horse".to_string()).await.unwrap();
+
+        let sessions = sessions::list_sessions(&db, &config).await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].username, Some("admin".to_string()));
+
+        sessions::logout(&admin, &db).await.unwrap();
+        let sessions = sessions::list_sessions(&db, &config).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        sessions::revoke_session(&db, "admin", sessions[0].session_id.clone()).await.unwrap();
+        assert!(sessions::list_sessions(&db, &config).await.unwrap().is_empty());
+
+        for _ in 0..3 {
+            if let AuthUser::NotApprovedTmpUser { id, .. } = create_tmp_user(&db, &config, "ip:1").await.unwrap() {
+                vouch_tmp_user(&db, &config, "ev".to_string(), id, "admin".to_string(), vec![]).await.unwrap();
+            }
+        }
+        assert_eq!(sessions::list_tmp_users(&db, Some("ev".to_string()), &config).await.unwrap().len(), 3);
+        assert!(sessions::list_tmp_users(&db, Some("other".to_string()), &config).await.unwrap().is_empty());
+        assert_eq!(sessions::revoke_event_helpers(&db, "admin", "ev".to_string()).await.unwrap(), 3);
+        assert!(sessions::list_tmp_users(&db, None, &config).await.unwrap().is_empty());
+    }
+
+    #[sqlx::test(migrator = "crate::auth::MIGRATOR")]
+    async fn pending_tmp_users(db: SqlitePool) {
+        let config = Config::default();
+        sqlx::query("INSERT INTO event(id, name) VALUES ('ev', 'Sportfest')").execute(&db).await.unwrap();
+        let mut ids = vec![];
+        for _ in 0..3 {
+            if let AuthUser::NotApprovedTmpUser { id, .. } = create_tmp_user(&db, &config, "ip:1").await.unwrap() {
+                ids.push(id);
+            }
+        }
+        // this request is too old
+        sqlx::query("UPDATE tmp_user SET time_of_creation = 0 WHERE id = ?").bind(&ids[2]).execute(&db).await.unwrap();
+        assert!(vouch_tmp_user(&db, &config, "ev".to_string(), ids[2].clone(), "admin".to_string(), vec![]).await.is_err());
+
+        let pending = sessions::list_pending_tmp_users(&db, &config).await.unwrap();
+        assert_eq!(pending.len(), 2);
+
+        vouch_tmp_user(&db, &config, "ev".to_string(), ids[0].clone(), "admin".to_string(), vec![]).await.unwrap();
+        let vouched = sessions::list_tmp_users(&db, Some("ev".to_string()), &config).await.unwrap();
+        assert_eq!(vouched[0].vouched_by, Some("admin".to_string()));
+        assert!(vouched[0].time_of_activation.is_some());
+
+        // only pending users can be rejected
+        assert!(sessions::reject_tmp_user(&db, "admin", ids[0].clone()).await.is_err());
+        sessions::reject_tmp_user(&db, "admin", ids[1].clone()).await.unwrap();
+        assert!(sessions::list_pending_tmp_users(&db, &config).await.unwrap().is_empty());
+
+        assert_eq!(sessions::expire_pending_tmp_users(&db, &config).await.unwrap(), 1);
+        let left: (i64,) = sqlx::query_as("SELECT count(*) FROM tmp_user").fetch_one(&db).await.unwrap();
+        assert_eq!(left.0, 1);
+    }
+
+    #[sqlx::test(migrator = "crate::auth::MIGRATOR")]
+    async fn redeem_join_code_once(db: SqlitePool) {
+        let config = Config::default();
+        sqlx::query("INSERT INTO event(id, name) VALUES ('ev', 'Sportfest')").execute(&db).await.unwrap();
+        assert!(join_codes::create_join_code(&db, &config, "ev".to_string(), "admin".to_string(), Some("king".to_string()), vec![]).await.is_err());
+        assert!(join_codes::create_join_code(&db, &config, "ev".to_string(), "admin".to_string(), Some(roles::ADMIN_ROLE.to_string()), vec![]).await.is_err());
+        let limiter = RateLimiter::new(&Config { max_failed_logins: 2, ..Config::default() });
+
+        let join_code = join_codes::create_join_code(&db, &config, "ev".to_string(), "admin".to_string(), None, vec![3]).await.unwrap();
+        assert!(join_code.qr_payload.ends_with(&join_code.code));
+
+        let (id, event_id) = match join_codes::redeem_join_code(&db, &limiter, "ip:1", join_code.code.to_lowercase()).await.unwrap() {
+            AuthUser::TmpUser { id, event_id, .. } => (id, event_id),
+            _ => panic!("a tmp user from a join code is vouched for")
+        };
+        assert_eq!(event_id, "ev");
+        assert_eq!(get_stations(&db, &id).await.unwrap(), vec![3]);
+        assert!(join_codes::redeem_join_code(&db, &limiter, "ip:1", join_code.code.clone()).await.is_err());
+
+        let expired = Config { join_code_lifetime: -1, ..Config::default() };
+        let join_code = join_codes::create_join_code(&db, &expired, "ev".to_string(), "admin".to_string(), None, vec![]).await.unwrap();
+        assert!(join_codes::redeem_join_code(&db, &limiter, "ip:1", join_code.code).await.is_err());
+        assert_eq!(join_codes::remove_old_join_codes(&db).await.unwrap(), 2);
+
+        // the ip guessed too many codes
+        let join_code = join_codes::create_join_code(&db, &config, "ev".to_string(), "admin".to_string(), None, vec![]).await.unwrap();
+        assert!(join_codes::redeem_join_code(&db, &limiter, "ip:1", join_code.code.clone()).await.is_err());
+        join_codes::redeem_join_code(&db, &limiter, "ip:2", join_code.code).await.unwrap();
+    }
+
+    #[sqlx::test(migrator = "crate::auth::MIGRATOR")]
+    async fn limit_pending_tmp_users(db: SqlitePool) {
+        let config = Config { max_pending_tmp_users: 2, ..Config::default() };
+        create_tmp_user(&db, &config, "ip:1").await.unwrap();
+        create_tmp_user(&db, &config, "ip:1").await.unwrap();
+        assert!(create_tmp_user(&db, &config, "ip:1").await.is_err());
+        // other clients can still ask to be vouched for
+        create_tmp_user(&db, &config, "ip:2").await.unwrap();
+
+        // a wrong password makes the ip wait, even for the right one, other ips can still login
+        let limiter = RateLimiter::new(&Config::default());
+        bootstrap_admin(&db, "admin".to_string(), "correct horse".to_string()).await.unwrap();
+        assert!(login(&db, &limiter, "ip:1", "admin".to_string(), "wrong password".to_string()).await.is_err());
+        assert!(login(&db, &limiter, "ip:1", "admin".to_string(), "correct horse".to_string()).await.is_err());
+        login(&db, &limiter, "ip:2", "admin".to_string(), "correct horse".to_string()).await.unwrap();
+    }
+
+    #[sqlx::test(migrator = "crate::auth::MIGRATOR")]
+    async fn cleanup_old_sessions(db: SqlitePool) {
+        let limiter = RateLimiter::new(&Config::default());
+        let config = Config { role_session_lifetimes: [("helper".to_string(), 60)].into(), ..Config::default() };
+        sqlx::query("INSERT INTO event(id, name) VALUES ('ev', 'Sportfest')").execute(&db).await.unwrap();
+        bootstrap_admin(&db, "admin".to_string(), "correct horse".to_string()).await.unwrap();
+        for _ in 0..3 {
+            login(&db, &limiter, "ip:1", "admin".to_string(), "correct horse".to_string()).await.unwrap();
+        }
+        let id = match create_tmp_user(&db, &config, "ip:1").await.unwrap() {
+            AuthUser::NotApprovedTmpUser { id, .. } => id,
+            _ => panic!("a new tmp user is not approved")
+        };
+        vouch_tmp_user(&db, &config, "ev".to_string(), id.clone(), "admin".to_string(), vec![]).await.unwrap();
+        assert_eq!(sessions::cleanup_sessions(&db, &config).await.unwrap(), 0);
+
+        let old = now() - 100_000;
+        // used all the time but too old
+        sqlx::query("UPDATE user_session SET time_of_creation = ? WHERE rowid = 1").bind(old).execute(&db).await.unwrap();
+        // not used for too long
+        sqlx::query("UPDATE user_session SET last_refresh = ? WHERE rowid = 2").bind(old).execute(&db).await.unwrap();
+        // helpers only stay valid for a minute
+        sqlx::query("UPDATE tmp_user SET last_refresh = ? WHERE id = ?").bind(now() - 120).bind(&id).execute(&db).await.unwrap();
+
+        // the lists agree with the cleanup
+        assert_eq!(sessions::list_sessions(&db, &config).await.unwrap().len(), 1);
+        assert!(sessions::list_tmp_users(&db, None, &config).await.unwrap().is_empty());
+        // a role with a longer lifetime keeps the unused session alive
+        let long = Config { role_session_lifetimes: [("admin".to_string(), 200_000)].into(), ..config.clone() };
+        assert_eq!(sessions::list_sessions(&db, &long).await.unwrap().len(), 2);
+
+        assert_eq!(sessions::cleanup_sessions(&db, &config).await.unwrap(), 3);
+        assert_eq!(sessions::list_sessions(&db, &config).await.unwrap().len(), 1);
+        let left: (i64,) = sqlx::query_as("SELECT count(*) FROM tmp_user").fetch_one(&db).await.unwrap();
+        assert_eq!(left.0, 0);
+    }
+
+
+    #[sqlx::test(migrator = "crate::auth::MIGRATOR")]
+    async fn audit_log_is_append_only(db: SqlitePool) {
+        let config = Config::default();
+        sqlx::query("INSERT INTO event(id, name) VALUES ('ev', 'Sportfest')").execute(&db).await.unwrap();
+        let id = match create_tmp_user(&db, &config, "ip:1").await.unwrap() {
+            AuthUser::NotApprovedTmpUser { id, .. } => id,
+            _ => panic!("a new tmp user is not approved")
+        };
+        vouch_tmp_user(&db, &config, "ev".to_string(), id.clone(), "admin".to_string(), vec![2]).await.unwrap();
+        let user = AuthUser::TmpUser { id: id.clone(), api_key: String::new(), event_id: "ev".to_string() };
+        audit::record(&db, AuditAction {
+            actor: user.name(),
+            action: "set_is_real",
+            event_id: user.event_id(),
+            target: Some("versuch:1".to_string()),
+            before: Some("true".to_string()),
+            after: Some("false".to_string()),
+        }).await;
+
+        let log = audit::get_audit_log(&db, Some("ev".to_string()), None).await.unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!((log[0].actor.as_str(), log[0].action.as_str()), ("admin", "vouch_tmp_user"));
+        let log = audit::get_audit_log(&db, Some("ev".to_string()), Some("versuch:1".to_string())).await.unwrap();
+        assert_eq!(log[0].actor, id);
+        let csv = audit::audit_log_csv(&log).unwrap();
+        assert!(csv.starts_with("id,time,actor,action,event_id,target,before,after\n"));
+
+        assert!(sqlx::query("UPDATE audit_log SET actor = 'nobody'").execute(&db).await.is_err());
+        assert!(sqlx::query("DELETE FROM audit_log").execute(&db).await.is_err());
+    }
 }
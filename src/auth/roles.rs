@@ -0,0 +1,228 @@
+//! Roles and the permissions that come with them
+//!
+//! Every admin and tmp user has a role. The roles and their permissions are stored in the auth
+//! db, so new roles can be added without changing the code. The permissions that are checked
+//! are:
+//! + `manage_events`
+//! + `upload_students`
+//! + `record_attempts`
+//! + `view_results`
+//! + `export`
+//!
+//! The permission is checked at runtime, an unknown permission is a bug of the endpoint and
+//! rejects every request. Endpoints with `ensure_admin` need the role `admin`.
+//!
+//! A endpoint can require a permission with the `ensure_permission` macro:
+//! ```
+//! use actix_web::{HttpRequest,HttpResponse};
+//! //#[ensure_permission("record_attempts")] // uncomment this
+//! pub async fn add_attempt(req: HttpRequest, data: i64) -> HttpResponse{
+//!     HttpResponse::Ok().into()
+//! }
+//! ```
+use sqlx::SqlitePool;
+use actix_web::HttpResponse;
+use serde::Serialize;
+use crate::{Forbidden, Forbiddenf, NotFoundf, InternalServerf, BadRequestf};
+use crate::audit::{self, AuditAction};
+use super::AuthUser;
+
+pub const PERMISSIONS: [&str; 5] = ["manage_events", "upload_students", "record_attempts", "view_results", "export"];
+/// the role that may use the endpoints with `ensure_admin`
+pub const ADMIN_ROLE: &str = "admin";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Role {
+    pub name: String,
+    pub permissions: Vec<String>,
+    /// class teachers may only see the students of this class
+    pub klasse: Option<String>,
+}
+
+impl Role {
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| p == permission)
+    }
+
+    /// if the user is allowed to see the students of a class. Students without a class can only
+    /// be seen by users that see all classes.
+    pub fn can_see_klasse(&self, klasse: Option<&str>) -> bool {
+        match &self.klasse {
+            Some(k) => Some(k.as_str()) == klasse,
+            None => true
+        }
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.name == ADMIN_ROLE
+    }
+
+    /// a role for tests, with all permissions
+    #[cfg(test)]
+    pub(crate) fn test(klasse: Option<&str>) -> Role {
+        Role {
+            name: ADMIN_ROLE.to_string(),
+            permissions: PERMISSIONS.iter().map(|p| p.to_string()).collect(),
+            klasse: klasse.map(|k| k.to_string()),
+        }
+    }
+}
+
+/// get the role of a user together with its permissions
+pub async fn get_role(user: &AuthUser, db: &SqlitePool) -> Result<Role, HttpResponse> {
+    let (name, klasse) = match user {
+        AuthUser::Admin { username, .. } | AuthUser::AdminWithEvent { username, .. } => {
+            match sqlx::query!("SELECT role, klasse FROM admin_user WHERE username = ?", username).fetch_one(db).await {
+                Ok(r) => (r.role, r.klasse),
+                Err(sqlx::Error::RowNotFound) => return Err(NotFoundf!("The admin {} was not found", username)),
+                Err(e) => return Err(InternalServerf!("Error while fetching the role of {} ({})", username, e))
+            }
+        },
+        AuthUser::TmpUser { id, .. } => {
+            match sqlx::query!("SELECT role FROM tmp_user WHERE id = ?", id).fetch_one(db).await {
+                Ok(r) => (r.role, None),
+                Err(sqlx::Error::RowNotFound) => return Err(NotFoundf!("The tmp_user {} was not found", id)),
+                Err(e) => return Err(InternalServerf!("Error while fetching the role of {} ({})", id, e))
+            }
+        },
+        AuthUser::NotApprovedTmpUser { .. } => return Err(Forbidden!("The user has not been vouched for"))
+    };
+
+    let permissions = match sqlx::query!("SELECT permission FROM role_permission WHERE role = ?", name).fetch_all(db).await {
+        Ok(r) => r.into_iter().map(|p| p.permission).collect(),
+        Err(e) => return Err(InternalServerf!("Error while fetching the permissions of the role {} ({})", name, e))
+    };
+
+    Ok(Role { name, permissions, klasse })
+}
+
+/// checks that the user has a permission and returns its role
+pub async fn check_permission(user: &AuthUser, permission: &str, db: &SqlitePool) -> Result<Role, HttpResponse> {
+    if !PERMISSIONS.contains(&permission) {
+        return Err(InternalServerf!("The permission {} does not exist", permission));
+    }
+    let role = get_role(user, db).await?;
+    if !role.has_permission(permission) {
+        return Err(Forbiddenf!("The role {} does not have the permission {}", role.name, permission));
+    }
+    Ok(role)
+}
+
+/// checks that the user is an admin with the role `admin` and returns its role
+pub async fn check_admin(user: &AuthUser, db: &SqlitePool) -> Result<Role, HttpResponse> {
+    if !matches!(user, AuthUser::Admin { .. } | AuthUser::AdminWithEvent { .. }) {
+        return Err(Forbidden!("Only admins can do this"));
+    }
+    let role = get_role(user, db).await?;
+    if !role.is_admin() {
+        return Err(Forbiddenf!("The role {} is not allowed to do this", role.name));
+    }
+    Ok(role)
+}
+
+/// all the roles with their permissions
+pub async fn get_roles(db: &SqlitePool) -> Result<Vec<Role>, HttpResponse> {
+    let rows = match sqlx::query!(r#"SELECT name, permission as "permission?" FROM role LEFT JOIN role_permission ON role = name ORDER BY name"#)
+        .fetch_all(db).await {
+            Ok(r) => r,
+            Err(e) => return Err(InternalServerf!("Error while fetching the roles ({})", e))
+    };
+
+    let mut roles: Vec<Role> = vec![];
+    for row in rows {
+        if roles.last().map(|r| r.name != row.name).unwrap_or(true) {
+            roles.push(Role { name: row.name.clone(), permissions: vec![], klasse: None });
+        }
+        if let Some(p) = row.permission {
+            roles.last_mut().unwrap().permissions.push(p);
+        }
+    }
+    Ok(roles)
+}
+
+/// creates a new role or replaces the permissions of an existing one. `actor` is written to the audit log.
+pub async fn set_role(db: &SqlitePool, actor: &str, name: String, permissions: Vec<String>) -> Result<(), HttpResponse> {
+    if let Some(p) = permissions.iter().find(|p| !PERMISSIONS.contains(&p.as_str())) {
+        return Err(BadRequestf!("The permission {} does not exist", p));
+    }
+
+    let mut tx = match db.begin().await {
+        Ok(t) => t,
+        Err(e) => return Err(InternalServerf!("Error while starting a transaction ({})", e))
+    };
+    if let Err(e) = sqlx::query!("INSERT OR IGNORE INTO role(name) VALUES (?)", name).execute(&mut *tx).await {
+        return Err(InternalServerf!("Error while inserting the role {} ({})", name, e));
+    }
+    if let Err(e) = sqlx::query!("DELETE FROM role_permission WHERE role = ?", name).execute(&mut *tx).await {
+        return Err(InternalServerf!("Error while removing the permissions of {} ({})", name, e));
+    }
+    for p in &permissions {
+        if let Err(e) = sqlx::query!("INSERT INTO role_permission(role, permission) VALUES (?,?)", name, p).execute(&mut *tx).await {
+            return Err(InternalServerf!("Error while adding the permission {} to {} ({})", p, name, e));
+        }
+    }
+    if let Err(e) = tx.commit().await {
+        return Err(InternalServerf!("Error while saving the role {} ({})", name, e));
+    }
+    audit::record(db, AuditAction {
+        actor,
+        action: "set_role",
+        target: Some(format!("role:{}", name)),
+        after: Some(permissions.join(",")),
+        ..Default::default()
+    }).await;
+    Ok(())
+}
+
+/// gives an admin a role. With `klasse` the admin only sees the students of that class.
+pub async fn set_admin_role(db: &SqlitePool, actor: &str, username: String, role: String, klasse: Option<String>) -> Result<(), HttpResponse> {
+    check_role_exists(db, &role).await?;
+    let rows_affected = match sqlx::query!("UPDATE admin_user SET role = ?, klasse = ? WHERE username = ?", role, klasse, username)
+        .execute(db).await {
+            Ok(r) => r.rows_affected(),
+            Err(e) => return Err(InternalServerf!("Error while setting the role of {} ({})", username, e))
+    };
+    if rows_affected == 0 {
+        return Err(NotFoundf!("The admin {} was not found", username));
+    }
+    let after = match klasse {
+        Some(k) => format!("role {} klasse {}", role, k),
+        None => format!("role {}", role)
+    };
+    audit::record(db, AuditAction {
+        actor,
+        action: "set_admin_role",
+        target: Some(format!("admin:{}", username)),
+        after: Some(after),
+        ..Default::default()
+    }).await;
+    Ok(())
+}
+
+/// gives a tmp user a role
+pub async fn set_tmp_user_role(db: &SqlitePool, actor: &str, tmp_user_id: String, role: String) -> Result<(), HttpResponse> {
+    check_role_exists(db, &role).await?;
+    let event_id = match sqlx::query!("UPDATE tmp_user SET role = ? WHERE id = ? RETURNING event_id", role, tmp_user_id)
+        .fetch_optional(db).await {
+            Ok(Some(r)) => r.event_id,
+            Ok(None) => return Err(NotFoundf!("The tmp_user {} was not found", tmp_user_id)),
+            Err(e) => return Err(InternalServerf!("Error while setting the role of {} ({})", tmp_user_id, e))
+    };
+    audit::record(db, AuditAction {
+        actor,
+        action: "set_tmp_user_role",
+        event_id: event_id.as_deref(),
+        target: Some(format!("tmp_user:{}", tmp_user_id)),
+        after: Some(format!("role {}", role)),
+        ..Default::default()
+    }).await;
+    Ok(())
+}
+
+pub(crate) async fn check_role_exists(db: &SqlitePool, role: &str) -> Result<(), HttpResponse> {
+    match sqlx::query!("SELECT name FROM role WHERE name = ?", role).fetch_optional(db).await {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(NotFoundf!("The role {} does not exist", role)),
+        Err(e) => Err(InternalServerf!("Error while fetching the role {} ({})", role, e))
+    }
+}
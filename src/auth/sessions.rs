@@ -0,0 +1,261 @@
+//! Listing and ending of sessions
+//!
+//! Admin sessions are identified by the hash of their key (`session_id`), so they can be listed
+//! and revoked without anyone seeing the keys. Tmp users are identified by their id.
+//!
+//! Tmp users that wait to be vouched for are pending. They can be listed and rejected, and
+//! expire after `pending_tmp_user_lifetime` seconds.
+//!
+//! If a session is still valid is decided by `session_expired` alone, so `get_user`, the lists
+//! and `cleanup_sessions` always agree.
+use sqlx::SqlitePool;
+use actix_web::HttpResponse;
+use serde::Serialize;
+use crate::{InternalServerf, NotFoundf, BadRequest};
+use crate::config::Config;
+use crate::audit::{self, AuditAction};
+use super::{AuthUser, hash_api_key, now};
+
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    pub session_id: String,
+    pub username: Option<String>,
+    pub time_of_creation: i64,
+    pub last_refresh: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TmpUserInfo {
+    pub id: String,
+    pub vouched: bool,
+    pub event_id: Option<String>,
+    pub role: String,
+    pub vouched_by: Option<String>,
+    pub time_of_creation: i64,
+    pub time_of_activation: Option<i64>,
+    pub last_refresh: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PendingTmpUser {
+    pub id: String,
+    pub time_of_creation: i64,
+}
+
+/// ends the session of the user that made the request
+pub async fn logout(user: &AuthUser, db: &SqlitePool) -> Result<(), HttpResponse> {
+    match user {
+        AuthUser::Admin { api_key, .. } | AuthUser::AdminWithEvent { api_key, .. } => delete_session(db, &hash_api_key(api_key)).await?,
+        AuthUser::TmpUser { id, .. } | AuthUser::NotApprovedTmpUser { id, .. } => delete_tmp_user(db, id).await?
+    }
+    audit::record(db, AuditAction { actor: user.name(), action: "logout", event_id: user.event_id(), ..Default::default() }).await;
+    Ok(())
+}
+
+/**
+ * Why a session is not valid anymore, `None` if it still is.
+ *
+ * A session expires when it was not used for the lifetime of its role (see
+ * `Config::session_lifetime`), when it is older than the max lifetime and, for tmp users, when
+ * nobody vouched for it in time. The reason is the message for the user.
+ */
+pub(crate) fn session_expired(config: &Config, role: &str, admin: bool, vouched: bool, time_of_creation: i64, last_refresh: i64, current_timestamp: i64) -> Option<&'static str> {
+    if current_timestamp - last_refresh > config.session_lifetime(role, admin) {
+        return Some("Sorry, key was not refreshed");
+    }
+    if admin {
+        if current_timestamp - time_of_creation > config.admin_session_max_lifetime {
+            return Some("The session is too old, please login again");
+        }
+        return None;
+    }
+    if current_timestamp - time_of_creation > config.tmp_session_max_lifetime {
+        return Some("The session is too old, please request a new one");
+    }
+    if !vouched && current_timestamp - time_of_creation > config.pending_tmp_user_lifetime {
+        return Some("Nobody vouched for this user in time, please request a new one");
+    }
+    None
+}
+
+/// all admin sessions that are still valid
+pub async fn list_sessions(db: &SqlitePool, config: &Config) -> Result<Vec<SessionInfo>, HttpResponse> {
+    let current_timestamp = now();
+    let sessions = match sqlx::query!(r#"
+        SELECT api_key, user_session.time_of_creation, last_refresh, user_session.username, role as "role?" FROM user_session
+        LEFT JOIN admin_user ON admin_user.username = user_session.username
+        ORDER BY last_refresh DESC
+    "#).fetch_all(db).await {
+        Ok(r) => r,
+        Err(e) => return Err(InternalServerf!("Error while fetching the sessions ({})", e))
+    };
+    Ok(sessions.into_iter()
+        .filter(|s| s.username.is_some()
+            && session_expired(config, s.role.as_deref().unwrap_or_default(), true, true, s.time_of_creation, s.last_refresh, current_timestamp).is_none())
+        .map(|s| SessionInfo { session_id: s.api_key, username: s.username, time_of_creation: s.time_of_creation, last_refresh: s.last_refresh })
+        .collect())
+}
+
+/// all tmp users that are still valid, optionally only those of one event
+pub async fn list_tmp_users(db: &SqlitePool, event_id: Option<String>, config: &Config) -> Result<Vec<TmpUserInfo>, HttpResponse> {
+    let current_timestamp = now();
+    match sqlx::query_as!(TmpUserInfo, r#"
+        SELECT id, vouched, event_id, role, vouched_by, time_of_creation, time_of_activation, last_refresh FROM tmp_user
+        WHERE ? IS NULL OR event_id = ?
+        ORDER BY last_refresh DESC
+    "#, event_id, event_id).fetch_all(db).await {
+        Ok(r) => Ok(r.into_iter()
+            .filter(|u| session_expired(config, &u.role, false, u.vouched, u.time_of_creation, u.last_refresh, current_timestamp).is_none())
+            .collect()),
+        Err(e) => Err(InternalServerf!("Error while fetching the tmp users ({})", e))
+    }
+}
+
+/// the tmp users that are waiting to be vouched for, oldest first
+pub async fn list_pending_tmp_users(db: &SqlitePool, config: &Config) -> Result<Vec<PendingTmpUser>, HttpResponse> {
+    let oldest_request = now() - config.pending_tmp_user_lifetime;
+    match sqlx::query_as!(PendingTmpUser, r#"
+        SELECT id, time_of_creation FROM tmp_user
+        WHERE vouched = false AND time_of_creation >= ?
+        ORDER BY time_of_creation
+    "#, oldest_request).fetch_all(db).await {
+        Ok(r) => Ok(r),
+        Err(e) => Err(InternalServerf!("Error while fetching the pending tmp users ({})", e))
+    }
+}
+
+/// rejects a tmp user that is waiting to be vouched for
+pub async fn reject_tmp_user(db: &SqlitePool, actor: &str, tmp_user_id: String) -> Result<(), HttpResponse> {
+    let rows_affected = match sqlx::query!("DELETE FROM tmp_user WHERE id = ? AND vouched = false", tmp_user_id).execute(db).await {
+        Ok(r) => r.rows_affected(),
+        Err(e) => return Err(InternalServerf!("Error while rejecting the tmp user {} ({})", tmp_user_id, e))
+    };
+    if rows_affected == 0 {
+        return Err(NotFoundf!("No pending tmp user {} was found", tmp_user_id));
+    }
+    audit::record(db, AuditAction { actor, action: "reject_tmp_user", target: Some(format!("tmp_user:{}", tmp_user_id)), ..Default::default() }).await;
+    Ok(())
+}
+
+/**
+ * Removes all tmp users that nobody vouched for in time.
+ *
+ * This should be called periodically by the server. Returns the number of removed users.
+ */
+pub async fn expire_pending_tmp_users(db: &SqlitePool, config: &Config) -> Result<u64, HttpResponse> {
+    let oldest_request = now() - config.pending_tmp_user_lifetime;
+    match sqlx::query!("DELETE FROM tmp_user WHERE vouched = false AND time_of_creation < ?", oldest_request).execute(db).await {
+        Ok(r) => Ok(r.rows_affected()),
+        Err(e) => Err(InternalServerf!("Error while removing the expired tmp users ({})", e))
+    }
+}
+
+/**
+ * Removes all sessions and tmp users that are not valid anymore, because they where not used
+ * for too long, are older than the max lifetime or nobody vouched for them in time. Admin
+ * sessions from before the login where introduced are removed as well.
+ *
+ * This should be called periodically by the server. Returns the number of removed rows.
+ */
+pub async fn cleanup_sessions(db: &SqlitePool, config: &Config) -> Result<u64, HttpResponse> {
+    let current_timestamp = now();
+    let mut tx = match db.begin().await {
+        Ok(t) => t,
+        Err(e) => return Err(InternalServerf!("Error while starting a transaction ({})", e))
+    };
+    let mut removed = 0;
+
+    let sessions = match sqlx::query!(r#"
+        SELECT api_key, user_session.time_of_creation, last_refresh, user_session.username, role as "role?" FROM user_session
+        LEFT JOIN admin_user ON admin_user.username = user_session.username
+    "#).fetch_all(&mut *tx).await {
+        Ok(r) => r,
+        Err(e) => return Err(InternalServerf!("Error while fetching the sessions ({})", e))
+    };
+    for s in sessions {
+        let expired = s.username.is_none()
+            || session_expired(config, s.role.as_deref().unwrap_or_default(), true, true, s.time_of_creation, s.last_refresh, current_timestamp).is_some();
+        if !expired {
+            continue;
+        }
+        if let Err(e) = sqlx::query!("DELETE FROM user_session WHERE api_key = ?", s.api_key).execute(&mut *tx).await {
+            return Err(InternalServerf!("Error while removing a session ({})", e));
+        }
+        removed += 1;
+    }
+
+    let tmp_users = match sqlx::query!("SELECT id, vouched, role, time_of_creation, last_refresh FROM tmp_user").fetch_all(&mut *tx).await {
+        Ok(r) => r,
+        Err(e) => return Err(InternalServerf!("Error while fetching the tmp users ({})", e))
+    };
+    for u in tmp_users {
+        let expired = session_expired(config, &u.role, false, u.vouched, u.time_of_creation, u.last_refresh, current_timestamp).is_some();
+        if !expired {
+            continue;
+        }
+        if let Err(e) = sqlx::query!("DELETE FROM tmp_user WHERE id = ?", u.id).execute(&mut *tx).await {
+            return Err(InternalServerf!("Error while removing the tmp user {} ({})", u.id, e));
+        }
+        removed += 1;
+    }
+
+    match tx.commit().await {
+        Ok(_) => Ok(removed),
+        Err(e) => Err(InternalServerf!("Error while removing the old sessions ({})", e))
+    }
+}
+
+/// ends a admin session
+pub async fn revoke_session(db: &SqlitePool, actor: &str, session_id: String) -> Result<(), HttpResponse> {
+    delete_session(db, &session_id).await?;
+    audit::record(db, AuditAction { actor, action: "revoke_session", target: Some(format!("session:{}", session_id)), ..Default::default() }).await;
+    Ok(())
+}
+
+/// removes a tmp user, its key is not valid anymore
+pub async fn revoke_tmp_user(db: &SqlitePool, actor: &str, tmp_user_id: String) -> Result<(), HttpResponse> {
+    delete_tmp_user(db, &tmp_user_id).await?;
+    audit::record(db, AuditAction { actor, action: "revoke_tmp_user", target: Some(format!("tmp_user:{}", tmp_user_id)), ..Default::default() }).await;
+    Ok(())
+}
+
+/// removes all tmp users of an event, e.g. after the event is over. Returns how many there where.
+pub async fn revoke_event_helpers(db: &SqlitePool, actor: &str, event_id: String) -> Result<u64, HttpResponse> {
+    if event_id.trim().is_empty() {
+        return Err(BadRequest!("No event was given"));
+    }
+    let removed = match sqlx::query!("DELETE FROM tmp_user WHERE event_id = ?", event_id).execute(db).await {
+        Ok(r) => r.rows_affected(),
+        Err(e) => return Err(InternalServerf!("Error while removing the tmp users of {} ({})", event_id, e))
+    };
+    audit::record(db, AuditAction {
+        actor,
+        action: "revoke_event_helpers",
+        event_id: Some(&event_id),
+        after: Some(format!("{} tmp users removed", removed)),
+        ..Default::default()
+    }).await;
+    Ok(removed)
+}
+
+async fn delete_session(db: &SqlitePool, session_id: &str) -> Result<(), HttpResponse> {
+    let rows_affected = match sqlx::query!("DELETE FROM user_session WHERE api_key = ?", session_id).execute(db).await {
+        Ok(r) => r.rows_affected(),
+        Err(e) => return Err(InternalServerf!("Error while ending the session ({})", e))
+    };
+    if rows_affected == 0 {
+        return Err(NotFoundf!("The session {} was not found", session_id));
+    }
+    Ok(())
+}
+
+async fn delete_tmp_user(db: &SqlitePool, tmp_user_id: &str) -> Result<(), HttpResponse> {
+    let rows_affected = match sqlx::query!("DELETE FROM tmp_user WHERE id = ?", tmp_user_id).execute(db).await {
+        Ok(r) => r.rows_affected(),
+        Err(e) => return Err(InternalServerf!("Error while removing the tmp user {} ({})", tmp_user_id, e))
+    };
+    if rows_affected == 0 {
+        return Err(NotFoundf!("The tmp user {} was not found", tmp_user_id));
+    }
+    Ok(())
+}
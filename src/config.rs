@@ -0,0 +1,241 @@
+//! The configuration of the library
+//!
+//! All the paths and limits that the server can change are in here. A config can be loaded from
+//! a toml file or from environment variables, everything that is not set uses the defaults.
+//! ```toml
+//! data_dir = "/srv/emotion/events"
+//! vorlagen_dir = "/srv/emotion/vorlagen"
+//! auth_db = "/srv/emotion/auth.db"
+//! admin_session_lifetime = 36000
+//! role_session_lifetimes = { helper = 3600 }
+//! schueler_ids = { start = 1000, end = 9999 }
+//! ```
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use actix_web::HttpResponse;
+use crate::BadRequestf;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// the directory with the event databases
+    pub data_dir: PathBuf,
+    /// the directory with the kategorie vorlagen, one sub directory per year
+    pub vorlagen_dir: PathBuf,
+    /// the auth database with the users, sessions, roles and the audit log
+    pub auth_db: PathBuf,
+    /// the database with the tables of the BJS
+    pub bjs_db: PathBuf,
+    /// the database with the tables of the DOSB
+    pub dosb_db: PathBuf,
+    /// seconds a tmp user session stays valid without being used
+    pub tmp_session_lifetime: i64,
+    /// seconds a admin session stays valid without being used
+    pub admin_session_lifetime: i64,
+    /// seconds a session of a role stays valid without being used, instead of the two above
+    pub role_session_lifetimes: HashMap<String, i64>,
+    /// seconds a tmp user session stays valid at most, even if it is used
+    pub tmp_session_max_lifetime: i64,
+    /// seconds a admin session stays valid at most, even if it is used
+    pub admin_session_max_lifetime: i64,
+    /// seconds a tmp user waits to be vouched for before the request expires
+    pub pending_tmp_user_lifetime: i64,
+    /// seconds a join code can be redeemed
+    pub join_code_lifetime: i64,
+    /// requests one api key may make per `rate_limit_window`
+    pub rate_limit_requests: u32,
+    /// seconds in which requests and unknown keys are counted
+    pub rate_limit_window: u64,
+    /// unknown keys per `rate_limit_window` before a ip is locked out
+    pub max_failed_logins: u32,
+    /// seconds a ip is locked out, also the longest wait after failed logins
+    pub lockout_time: u64,
+    /// tmp users of one ip that may wait to be vouched for at the same time
+    pub max_pending_tmp_users: i64,
+    /// seconds a event stays open in the registry without being used
+    pub event_idle_timeout: u64,
+    /// a toml file with the layout of the urkunden, see `export::urkunden`. Without one the
+    /// default layout is used.
+    pub urkunden_template: Option<PathBuf>,
+    /// the ids students can have
+    pub schueler_ids: Range<i64>,
+    /// the ages students can have
+    pub ages: Range<i8>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            data_dir: PathBuf::from("db/"),
+            vorlagen_dir: PathBuf::from("vorlagen/"),
+            auth_db: PathBuf::from("db/auth.db"),
+            bjs_db: PathBuf::from("db/bjs.db"),
+            dosb_db: PathBuf::from("db/dosb.db"),
+            tmp_session_lifetime: 18000,
+            admin_session_lifetime: 36000,
+            role_session_lifetimes: HashMap::new(),
+            tmp_session_max_lifetime: 43200,
+            admin_session_max_lifetime: 86400,
+            pending_tmp_user_lifetime: 900,
+            join_code_lifetime: 600,
+            rate_limit_requests: 300,
+            rate_limit_window: 60,
+            max_failed_logins: 20,
+            lockout_time: 900,
+            max_pending_tmp_users: 50,
+            event_idle_timeout: 3600,
+            urkunden_template: None,
+            schueler_ids: 1000..9999,
+            ages: 5..25,
+        }
+    }
+}
+
+impl Config {
+    /// loads the config from a toml file
+    pub fn from_file(path: &Path) -> Result<Config, String> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => return Err(format!("Couldnt read config file {}: {}", path.display(), e))
+        };
+        match toml::from_str(&content) {
+            Ok(c) => Ok(c),
+            Err(e) => Err(format!("Couldnt parse config file {}: {}", path.display(), e))
+        }
+    }
+
+    /**
+     * loads the config from environment variables
+     *
+     * The variables are called like the fields with an `EMOTION_` prefix, e.g. `EMOTION_DATA_DIR`.
+     * Ranges are given as `start..end` and the session lifetimes of roles as
+     * `helper=3600,admin=7200`.
+     */
+    pub fn from_env() -> Result<Config, String> {
+        let mut config = Config::default();
+        if let Some(v) = env_var("EMOTION_DATA_DIR") { config.data_dir = PathBuf::from(v) }
+        if let Some(v) = env_var("EMOTION_VORLAGEN_DIR") { config.vorlagen_dir = PathBuf::from(v) }
+        if let Some(v) = env_var("EMOTION_AUTH_DB") { config.auth_db = PathBuf::from(v) }
+        if let Some(v) = env_var("EMOTION_BJS_DB") { config.bjs_db = PathBuf::from(v) }
+        if let Some(v) = env_var("EMOTION_DOSB_DB") { config.dosb_db = PathBuf::from(v) }
+        if let Some(v) = env_var("EMOTION_TMP_SESSION_LIFETIME") { config.tmp_session_lifetime = parse_var("EMOTION_TMP_SESSION_LIFETIME", &v)? }
+        if let Some(v) = env_var("EMOTION_ADMIN_SESSION_LIFETIME") { config.admin_session_lifetime = parse_var("EMOTION_ADMIN_SESSION_LIFETIME", &v)? }
+        if let Some(v) = env_var("EMOTION_ROLE_SESSION_LIFETIMES") { config.role_session_lifetimes = parse_map("EMOTION_ROLE_SESSION_LIFETIMES", &v)? }
+        if let Some(v) = env_var("EMOTION_TMP_SESSION_MAX_LIFETIME") { config.tmp_session_max_lifetime = parse_var("EMOTION_TMP_SESSION_MAX_LIFETIME", &v)? }
+        if let Some(v) = env_var("EMOTION_ADMIN_SESSION_MAX_LIFETIME") { config.admin_session_max_lifetime = parse_var("EMOTION_ADMIN_SESSION_MAX_LIFETIME", &v)? }
+        if let Some(v) = env_var("EMOTION_PENDING_TMP_USER_LIFETIME") { config.pending_tmp_user_lifetime = parse_var("EMOTION_PENDING_TMP_USER_LIFETIME", &v)? }
+        if let Some(v) = env_var("EMOTION_JOIN_CODE_LIFETIME") { config.join_code_lifetime = parse_var("EMOTION_JOIN_CODE_LIFETIME", &v)? }
+        if let Some(v) = env_var("EMOTION_RATE_LIMIT_REQUESTS") { config.rate_limit_requests = parse_var("EMOTION_RATE_LIMIT_REQUESTS", &v)? }
+        if let Some(v) = env_var("EMOTION_RATE_LIMIT_WINDOW") { config.rate_limit_window = parse_var("EMOTION_RATE_LIMIT_WINDOW", &v)? }
+        if let Some(v) = env_var("EMOTION_MAX_FAILED_LOGINS") { config.max_failed_logins = parse_var("EMOTION_MAX_FAILED_LOGINS", &v)? }
+        if let Some(v) = env_var("EMOTION_LOCKOUT_TIME") { config.lockout_time = parse_var("EMOTION_LOCKOUT_TIME", &v)? }
+        if let Some(v) = env_var("EMOTION_MAX_PENDING_TMP_USERS") { config.max_pending_tmp_users = parse_var("EMOTION_MAX_PENDING_TMP_USERS", &v)? }
+        if let Some(v) = env_var("EMOTION_EVENT_IDLE_TIMEOUT") { config.event_idle_timeout = parse_var("EMOTION_EVENT_IDLE_TIMEOUT", &v)? }
+        if let Some(v) = env_var("EMOTION_URKUNDEN_TEMPLATE") { config.urkunden_template = Some(PathBuf::from(v)) }
+        if let Some(v) = env_var("EMOTION_SCHUELER_IDS") { config.schueler_ids = parse_range("EMOTION_SCHUELER_IDS", &v)? }
+        if let Some(v) = env_var("EMOTION_AGES") { config.ages = parse_range("EMOTION_AGES", &v)? }
+        Ok(config)
+    }
+
+    /**
+     * The path of the database of an event.
+     *
+     * The id ends up in a file path, so only letters, digits, `_` and `-` are allowed. Every
+     * access to an event file goes through this, so ids like `../auth` can't reach other files.
+     */
+    pub fn event_db(&self, id: &str) -> Result<String, HttpResponse> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(BadRequestf!("The event id {} is not valid", id));
+        }
+        Ok(self.data_dir.join(format!("{}.db", id)).to_string_lossy().to_string())
+    }
+
+    /// seconds a session of the role stays valid without being used
+    pub fn session_lifetime(&self, role: &str, admin: bool) -> i64 {
+        match self.role_session_lifetimes.get(role) {
+            Some(l) => *l,
+            None if admin => self.admin_session_lifetime,
+            None => self.tmp_session_lifetime
+        }
+    }
+
+    /// the directory with the vorlagen of one year
+    pub fn vorlagen_year_dir(&self, year: i64) -> PathBuf {
+        self.vorlagen_dir.join(year.to_string())
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.trim().is_empty())
+}
+
+fn parse_var<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    match value.trim().parse() {
+        Ok(v) => Ok(v),
+        Err(_) => Err(format!("The value {} of {} is not valid", value, name))
+    }
+}
+
+fn parse_range<T: FromStr>(name: &str, value: &str) -> Result<Range<T>, String> {
+    match value.split_once("..") {
+        Some((start, end)) => Ok(parse_var(name, start)?..parse_var(name, end)?),
+        None => Err(format!("{} has to be a range like 1000..9999 but is {}", name, value))
+    }
+}
+
+fn parse_map<T: FromStr>(name: &str, value: &str) -> Result<HashMap<String, T>, String> {
+    let mut map = HashMap::new();
+    for entry in value.split(',').filter(|e| !e.trim().is_empty()) {
+        match entry.split_once('=') {
+            Some((k, v)) => map.insert(k.trim().to_string(), parse_var(name, v)?),
+            None => return Err(format!("{} has to be a list like helper=3600,admin=7200 but is {}", name, value))
+        };
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_partial_toml() {
+        let config: Config = toml::from_str(r#"
+            data_dir = "/srv/events"
+            bjs_db = "/srv/bjs.db"
+            tmp_session_lifetime = 600
+            schueler_ids = { start = 10000, end = 99999 }
+        "#).unwrap();
+        assert_eq!(config.data_dir, PathBuf::from("/srv/events"));
+        assert_eq!(config.tmp_session_lifetime, 600);
+        assert_eq!(config.schueler_ids, 10000..99999);
+        // everything else is the default
+        assert_eq!(config.admin_session_lifetime, 36000);
+        assert_eq!(config.ages, 5..25);
+        assert_eq!(config.bjs_db, PathBuf::from("/srv/bjs.db"));
+        assert_eq!(config.auth_db, PathBuf::from("db/auth.db"));
+        assert_eq!(config.event_db("abc").unwrap(), "/srv/events/abc.db");
+        assert!(config.event_db("../auth").is_err());
+        assert!(config.event_db("").is_err());
+    }
+
+    #[test]
+    fn parse_ranges() {
+        assert_eq!(parse_range::<i64>("X", "1..10").unwrap(), 1..10);
+        assert!(parse_range::<i64>("X", "10").is_err());
+        assert!(parse_range::<i8>("X", "1..1000").is_err());
+    }
+
+    #[test]
+    fn session_lifetimes_of_roles() {
+        let config = Config { role_session_lifetimes: parse_map("X", "helper=600, head_teacher=7200").unwrap(), ..Config::default() };
+        assert_eq!(config.session_lifetime("helper", false), 600);
+        assert_eq!(config.session_lifetime("head_teacher", true), 7200);
+        assert_eq!(config.session_lifetime("admin", true), 36000);
+        assert_eq!(config.session_lifetime("jury", false), 18000);
+        assert!(parse_map::<i64>("X", "helper").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! The class competition
+//!
+//! Many schools crown the best class of an event. The classes are ranked by one of the rules of
+//! `Wertung`, all other values are still calculated so they can be shown next to it.
+//! A student takes part once the BJS evaluation gives any urkunde.
+use actix_web::HttpResponse;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use crate::bjs_eval::BJSAbzeichen;
+use super::{ResultRow, get_results};
+use crate::auth::roles::Role;
+use super::rangliste::plaetze;
+
+/// how the classes are ranked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Wertung {
+    /// the average BJS points of the students that took part
+    Durchschnitt,
+    /// the share of the students that took part with a sieger- or ehrenurkunde
+    Urkundenanteil,
+    /// the share of the students of the class that took part
+    Teilnahme,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KlassenStand {
+    pub platz: u32,
+    pub klasse: String,
+    pub schueler: u32,
+    pub teilnehmer: u32,
+    pub sieger_ehren: u32,
+    pub durchschnitt: f64,
+    pub urkundenanteil: f64,
+    pub teilnahme: f64,
+    /// the value the class is ranked by
+    pub wert: f64,
+}
+
+/// the standings of all classes of an event, the best first
+pub async fn get_klassenwertung(wertung: Wertung, role: &Role, db: &SqlitePool, dosb_db: &SqlitePool, bjs_db: &SqlitePool) -> Result<Vec<KlassenStand>, HttpResponse> {
+    Ok(klassenwertung(&get_results(role, db, dosb_db, bjs_db).await?, wertung))
+}
+
+/// the standings of the classes of the results, the best first
+pub fn klassenwertung(results: &[ResultRow], wertung: Wertung) -> Vec<KlassenStand> {
+    let mut klassen: BTreeMap<&str, Vec<&ResultRow>> = BTreeMap::new();
+    for r in results {
+        klassen.entry(r.klasse.as_str()).or_default().push(r);
+    }
+
+    let mut staende: Vec<KlassenStand> = klassen.into_iter()
+        .map(|(klasse, rows)| {
+            let teilnehmer: Vec<&&ResultRow> = rows.iter().filter(|r| r.bjs_urkunde != BJSAbzeichen::None).collect();
+            let sieger_ehren = teilnehmer.iter()
+                .filter(|r| matches!(r.bjs_urkunde, BJSAbzeichen::Sieger | BJSAbzeichen::Ehren))
+                .count() as u32;
+            let punkte: i64 = teilnehmer.iter().map(|r| r.bjs_punkte).sum();
+            let durchschnitt = share(punkte as f64, teilnehmer.len());
+            let urkundenanteil = share(sieger_ehren as f64, teilnehmer.len());
+            let teilnahme = share(teilnehmer.len() as f64, rows.len());
+            KlassenStand {
+                platz: 0,
+                klasse: klasse.to_string(),
+                schueler: rows.len() as u32,
+                teilnehmer: teilnehmer.len() as u32,
+                sieger_ehren,
+                durchschnitt,
+                urkundenanteil,
+                teilnahme,
+                wert: match wertung {
+                    Wertung::Durchschnitt => durchschnitt,
+                    Wertung::Urkundenanteil => urkundenanteil,
+                    Wertung::Teilnahme => teilnahme,
+                },
+            }
+        })
+        .collect();
+
+    staende.sort_by(|a, b| b.wert.total_cmp(&a.wert).then_with(|| a.klasse.cmp(&b.klasse)));
+    let werte: Vec<f64> = staende.iter().map(|s| s.wert).collect();
+    for (s, platz) in staende.iter_mut().zip(plaetze(&werte)) {
+        s.platz = platz;
+    }
+    staende
+}
+
+fn share(part: f64, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(klasse: &str, bjs_punkte: i64, bjs_urkunde: BJSAbzeichen) -> ResultRow {
+        ResultRow { klasse: klasse.to_string(), bjs_punkte, bjs_urkunde, ..ResultRow::test(1000) }
+    }
+
+    #[test]
+    fn rank_classes() {
+        let results = vec![
+            row("7a", 1200, BJSAbzeichen::Ehren),
+            row("7a", 600, BJSAbzeichen::Teilnehmer),
+            row("7a", 0, BJSAbzeichen::None),
+            row("7b", 800, BJSAbzeichen::Sieger),
+            row("7b", 800, BJSAbzeichen::Sieger),
+            row("7c", 950, BJSAbzeichen::Sieger),
+        ];
+
+        let staende = klassenwertung(&results, Wertung::Durchschnitt);
+        let klassen: Vec<(&str, u32)> = staende.iter().map(|s| (s.klasse.as_str(), s.platz)).collect();
+        assert_eq!(klassen, vec![("7c", 1), ("7a", 2), ("7b", 3)]);
+        assert_eq!(staende[1].durchschnitt, 900.0);
+        assert_eq!(staende[1].schueler, 3);
+        assert_eq!(staende[1].teilnehmer, 2);
+
+        // 7b and 7c share the first place
+        let staende = klassenwertung(&results, Wertung::Urkundenanteil);
+        let klassen: Vec<(&str, u32)> = staende.iter().map(|s| (s.klasse.as_str(), s.platz)).collect();
+        assert_eq!(klassen, vec![("7b", 1), ("7c", 1), ("7a", 3)]);
+
+        let staende = klassenwertung(&results, Wertung::Teilnahme);
+        assert_eq!(staende.last().unwrap().klasse, "7a");
+    }
+}
@@ -0,0 +1,293 @@
+//! Result lists of a class or the whole event as CSV or XLSX
+//!
+//! Every student gets a row with its best result in each kategorie, the BJS points and urkunde and
+//! the DOSB points and abzeichen. The results are formatted with the `digits_before` and
+//! `digits_after` of their kategorie, like in the input mask.
+use actix_web::HttpResponse;
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use rust_xlsxwriter::{Format, Workbook};
+use crate::InternalServerf;
+use crate::search::{result2extensive, search_schema};
+use super::{ResultRow, get_results, bjs_urkunde_name, dosb_abzeichen_name};
+use crate::auth::roles::Role;
+
+#[derive(Debug, Clone)]
+pub struct ListenKategorie {
+    pub id: i64,
+    pub name: String,
+    pub einheit: String,
+    pub digits_before: i64,
+    pub digits_after: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ListenRow {
+    pub result: ResultRow,
+    /// the best result of each kategorie, by the id of the kategorie
+    pub best: HashMap<i64, f64>,
+}
+
+/// the data of a result list, the kategorien are the columns
+#[derive(Debug, Clone)]
+pub struct ResultList {
+    pub kategorien: Vec<ListenKategorie>,
+    pub rows: Vec<ListenRow>,
+}
+
+/**
+ * The result list of a class, or of the whole event if `klasse` is `None`.
+ *
+ * Only the kategorien somebody has a result in become columns.
+ */
+pub async fn get_result_list(klasse: Option<String>, role: &Role, db: &SqlitePool, dosb_db: &SqlitePool, bjs_db: &SqlitePool) -> Result<ResultList, HttpResponse> {
+    let mut results = get_results(role, db, dosb_db, bjs_db).await?;
+    if let Some(k) = &klasse {
+        results.retain(|r| &r.klasse == k);
+    }
+
+    let mut rows = vec![];
+    for result in results {
+        let extensive = result2extensive(search_schema::SchuelerResult {
+            id: result.id,
+            bjs_punkte: result.bjs_punkte,
+            bjs_urkunde: result.bjs_urkunde,
+            dosb_punkte: result.dosb_punkte,
+            dosb_abzeichen: result.dosb_abzeichen,
+        }, db, dosb_db, bjs_db).await?;
+
+        let mut best = HashMap::new();
+        for a in extensive.single_results {
+            best.entry(a.category).or_insert(a.result);
+        }
+        rows.push(ListenRow { result, best });
+    }
+
+    let kategorien = get_kategorien(db).await?.into_iter()
+        .filter(|k| rows.iter().any(|r| r.best.contains_key(&k.id)))
+        .collect();
+
+    Ok(ResultList { kategorien, rows })
+}
+
+/// all kategorien of an event, sorted by id
+pub async fn get_kategorien(db: &SqlitePool) -> Result<Vec<ListenKategorie>, HttpResponse> {
+    let kategorien = match sqlx::query!("SELECT id, name, einheit, digits_before, digits_after FROM kategorien ORDER BY id")
+        .fetch_all(db).await {
+            Ok(r) => r,
+            Err(e) => return Err(InternalServerf!("Error while fetching the kategorien ({})", e))
+    };
+    Ok(kategorien.into_iter()
+        .map(|k| ListenKategorie {
+            id: k.id,
+            name: k.name.unwrap_or_default(),
+            einheit: k.einheit.unwrap_or_default(),
+            digits_before: k.digits_before.unwrap_or(1),
+            digits_after: k.digits_after.unwrap_or(2),
+        })
+        .collect())
+}
+
+/// formats a result with a german decimal comma, e.g. `format_wert(7.5, 1, 2)` is `7,50`
+pub fn format_wert(wert: f64, digits_before: i64, digits_after: i64) -> String {
+    let digits_after = digits_after.max(0) as usize;
+    // the width includes the comma
+    let width = digits_before.max(1) as usize + if digits_after > 0 { digits_after + 1 } else { 0 };
+    format!("{:0width$.digits_after$}", wert, width = width, digits_after = digits_after).replace('.', ",")
+}
+
+/// the german column headers of a result list
+pub fn headers(list: &ResultList) -> Vec<String> {
+    let mut headers: Vec<String> = ["Nr", "Nachname", "Vorname", "Klasse", "Geschlecht", "Alter"].iter().map(|h| h.to_string()).collect();
+    headers.extend(list.kategorien.iter().map(|k| format!("{} ({})", k.name, k.einheit)));
+    headers.extend(["BJS Punkte", "Urkunde", "DOSB Punkte", "Abzeichen"].iter().map(|h| h.to_string()));
+    headers
+}
+
+/// the result list as csv, separated by `;` so it opens in a german excel
+pub fn result_list_csv(list: &ResultList) -> Result<String, HttpResponse> {
+    let mut writer = csv::WriterBuilder::new().delimiter(b';').from_writer(vec![]);
+    if let Err(e) = writer.write_record(headers(list)) {
+        return Err(InternalServerf!("Error while writing the result list as csv ({})", e));
+    }
+    for row in &list.rows {
+        let r = &row.result;
+        let mut record = vec![r.id.to_string(), r.lname.clone(), r.fname.clone(), r.klasse.clone(), r.gesch.to_string(), r.age.to_string()];
+        record.extend(list.kategorien.iter().map(|k| match row.best.get(&k.id) {
+            Some(w) => format_wert(*w, k.digits_before, k.digits_after),
+            None => String::new()
+        }));
+        record.extend([
+            r.bjs_punkte.to_string(),
+            bjs_urkunde_name(r.bjs_urkunde).unwrap_or_default().to_string(),
+            r.dosb_punkte.to_string(),
+            dosb_abzeichen_name(r.dosb_abzeichen).unwrap_or_default().to_string(),
+        ]);
+        if let Err(e) = writer.write_record(record) {
+            return Err(InternalServerf!("Error while writing the result list as csv ({})", e));
+        }
+    }
+    match writer.into_inner() {
+        Ok(b) => Ok(String::from_utf8_lossy(&b).to_string()),
+        Err(e) => Err(InternalServerf!("Error while writing the result list as csv ({})", e))
+    }
+}
+
+/**
+ * The result list as xlsx with one worksheet per class.
+ *
+ * The results are numbers formatted with the digits of their kategorie, so they can still be
+ * calculated with.
+ */
+pub fn result_list_xlsx(list: &ResultList) -> Result<Vec<u8>, HttpResponse> {
+    let mut workbook = Workbook::new();
+    let bold = Format::new().set_bold();
+    let headers = headers(list);
+    let formats: Vec<Format> = list.kategorien.iter()
+        .map(|k| Format::new().set_num_format(number_format(k.digits_before, k.digits_after)))
+        .collect();
+
+    let mut klassen: BTreeMap<&str, Vec<&ListenRow>> = BTreeMap::new();
+    for row in &list.rows {
+        klassen.entry(row.result.klasse.as_str()).or_default().push(row);
+    }
+    if klassen.is_empty() {
+        klassen.insert("", vec![]);
+    }
+
+    let mut used_names = HashSet::new();
+    for (klasse, rows) in klassen {
+        let sheet = workbook.add_worksheet();
+        if let Err(e) = sheet.set_name(sheet_name(klasse, &mut used_names)) {
+            return Err(InternalServerf!("Error while naming the worksheet of {} ({})", klasse, e));
+        }
+        if let Err(e) = sheet.write_row_with_format(0, 0, &headers, &bold) {
+            return Err(InternalServerf!("Error while writing the result list as xlsx ({})", e));
+        }
+
+        for (i, row) in rows.into_iter().enumerate() {
+            let r = &row.result;
+            let line = i as u32 + 1;
+            let kat_col = 6;
+            let end_col = kat_col + list.kategorien.len() as u16;
+
+            let written = sheet.write_number(line, 0, r.id as f64)
+                .and_then(|s| s.write_string(line, 1, &r.lname))
+                .and_then(|s| s.write_string(line, 2, &r.fname))
+                .and_then(|s| s.write_string(line, 3, &r.klasse))
+                .and_then(|s| s.write_string(line, 4, r.gesch.to_string()))
+                .and_then(|s| s.write_number(line, 5, r.age as f64))
+                .and_then(|s| s.write_number(line, end_col, r.bjs_punkte as f64))
+                .and_then(|s| s.write_string(line, end_col + 1, bjs_urkunde_name(r.bjs_urkunde).unwrap_or_default()))
+                .and_then(|s| s.write_number(line, end_col + 2, r.dosb_punkte as f64))
+                .and_then(|s| s.write_string(line, end_col + 3, dosb_abzeichen_name(r.dosb_abzeichen).unwrap_or_default()));
+            if let Err(e) = written {
+                return Err(InternalServerf!("Error while writing the result list as xlsx ({})", e));
+            }
+
+            for (j, k) in list.kategorien.iter().enumerate() {
+                if let Some(w) = row.best.get(&k.id) {
+                    if let Err(e) = sheet.write_number_with_format(line, kat_col + j as u16, *w, &formats[j]) {
+                        return Err(InternalServerf!("Error while writing the result list as xlsx ({})", e));
+                    }
+                }
+            }
+        }
+    }
+
+    match workbook.save_to_buffer() {
+        Ok(b) => Ok(b),
+        Err(e) => Err(InternalServerf!("Error while writing the result list as xlsx ({})", e))
+    }
+}
+
+/// the excel number format of a kategorie, e.g. `0.00`
+fn number_format(digits_before: i64, digits_after: i64) -> String {
+    let mut format = "0".repeat(digits_before.max(1) as usize);
+    if digits_after > 0 {
+        format.push('.');
+        format.push_str(&"0".repeat(digits_after as usize));
+    }
+    format
+}
+
+/**
+ * Excel only allows 31 characters and no `[]:*?/\` in the names of worksheets, and no two sheets
+ * with the same name (ignoring the case). Classes that end up with a taken name get a number,
+ * e.g. `7_a (2)`. `used` are the names of the sheets so far.
+ */
+fn sheet_name(klasse: &str, used: &mut HashSet<String>) -> String {
+    let name: String = klasse.chars()
+        .map(|c| if "[]:*?/\\".contains(c) { '_' } else { c })
+        .collect();
+    let name = if name.trim().is_empty() { "Ohne Klasse".to_string() } else { name };
+
+    let mut number = 1;
+    loop {
+        let suffix = if number == 1 { String::new() } else { format!(" ({})", number) };
+        let candidate: String = name.chars().take(31 - suffix.len()).chain(suffix.chars()).collect();
+        if used.insert(candidate.to_lowercase()) {
+            return candidate;
+        }
+        number += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bjs_eval::BJSAbzeichen;
+    use crate::dosb_eval::DOSBAbzeichen;
+
+    fn list() -> ResultList {
+        let result = ResultRow {
+            fname: "Anna".to_string(),
+            lname: "Müller".to_string(),
+            bjs_punkte: 1100,
+            bjs_urkunde: BJSAbzeichen::Sieger,
+            dosb_punkte: 7,
+            dosb_abzeichen: DOSBAbzeichen::Silber,
+            ..ResultRow::test(1234)
+        };
+        ResultList {
+            kategorien: vec![
+                ListenKategorie { id: 1, name: "Sprint".to_string(), einheit: "s".to_string(), digits_before: 2, digits_after: 1 },
+                ListenKategorie { id: 4, name: "Weitsprung".to_string(), einheit: "m".to_string(), digits_before: 1, digits_after: 2 },
+            ],
+            rows: vec![
+                ListenRow { result: result.clone(), best: [(1, 8.26), (4, 3.5)].into() },
+                ListenRow { result: ResultRow { id: 1240, klasse: "7b".to_string(), ..result }, best: [(4, 4.0)].into() },
+            ],
+        }
+    }
+
+    #[test]
+    fn format_results() {
+        assert_eq!(format_wert(7.5, 1, 2), "7,50");
+        assert_eq!(format_wert(13.24, 2, 1), "13,2");
+        assert_eq!(format_wert(7.5, 2, 1), "07,5");
+        assert_eq!(format_wert(2345.0, 3, 0), "2345");
+        assert_eq!(number_format(2, 1), "00.0");
+        let mut used = HashSet::new();
+        assert_eq!(sheet_name("7/a", &mut used), "7_a");
+        assert_eq!(sheet_name("7_A", &mut used), "7_A (2)");
+        assert_eq!(sheet_name("", &mut used), "Ohne Klasse");
+        assert_eq!(sheet_name(" ", &mut used), "Ohne Klasse (2)");
+        let long = "Leistungskurs Sport Jahrgang 12";
+        assert_eq!(sheet_name(long, &mut used), long);
+        assert_eq!(sheet_name(&format!("{} b", long), &mut used), "Leistungskurs Sport Jahrgan (2)");
+    }
+
+    #[test]
+    fn result_list_files() {
+        let list = list();
+        let csv = result_list_csv(&list).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next().unwrap(), "Nr;Nachname;Vorname;Klasse;Geschlecht;Alter;Sprint (s);Weitsprung (m);BJS Punkte;Urkunde;DOSB Punkte;Abzeichen");
+        assert_eq!(lines.next().unwrap(), "1234;Müller;Anna;7a;w;12;08,3;3,50;1100;Siegerurkunde;7;Silber");
+        assert_eq!(lines.next().unwrap(), "1240;Müller;Anna;7b;w;12;;4,00;1100;Siegerurkunde;7;Silber");
+
+        let xlsx = result_list_xlsx(&list).unwrap();
+        assert!(xlsx.starts_with(b"PK"));
+    }
+}
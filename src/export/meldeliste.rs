@@ -0,0 +1,200 @@
+//! The DOSB Sportabzeichen list for the submission to the sports association
+//!
+//! For every student that got an abzeichen the list has the best attempt of each of the four
+//! groups with the stufe (bronze, silber or gold) reached in it, the points and the abzeichen,
+//! like on the Prüfkarte. The stufe of an attempt comes from `DOSBEvaluator::get_medal_for_attempt`,
+//! the abzeichen from `DOSBEvaluator::get_medal`.
+use actix_web::HttpResponse;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use crate::InternalServerf;
+use crate::dosb_eval::{DOSBAbzeichen, DOSBEvaluator};
+use crate::model::Attempt;
+use super::{ResultRow, get_results, dosb_abzeichen_name};
+use crate::auth::roles::Role;
+use super::listen::{ListenKategorie, get_kategorien, format_wert};
+
+/// the ids of the four groups of the DOSB
+const GRUPPEN: [i64; 4] = [1, 2, 3, 4];
+
+#[derive(Debug, Clone)]
+pub struct DOSBGruppe {
+    pub id: i64,
+    pub name: String,
+}
+
+/// the attempt of a student that counts for a group
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GruppenErgebnis {
+    pub attempt: Attempt,
+    pub stufe: DOSBAbzeichen,
+}
+
+#[derive(Debug, Clone)]
+pub struct DOSBMeldung {
+    pub result: ResultRow,
+    /// by the id of the group
+    pub gruppen: HashMap<i64, GruppenErgebnis>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DOSBMeldeliste {
+    pub gruppen: Vec<DOSBGruppe>,
+    pub kategorien: Vec<ListenKategorie>,
+    pub rows: Vec<DOSBMeldung>,
+}
+
+/**
+ * The best attempt of each group, that is the one with the highest stufe.
+ *
+ * Groups without an attempt are missing, an attempt that did not reach bronze is kept with
+ * `DOSBAbzeichen::None`.
+ */
+pub async fn get_gruppen_ergebnisse(dosb_db: &SqlitePool, age: i64, gender: char, attempts: Vec<Attempt>) -> Result<HashMap<i64, GruppenErgebnis>, HttpResponse> {
+    let evaluator = DOSBEvaluator { db: dosb_db };
+    let mut gruppen: HashMap<i64, GruppenErgebnis> = HashMap::new();
+    for attempt in evaluator.get_top_attempts(age, gender, attempts).await? {
+        let gruppe = match sqlx::query!("SELECT category_group_id FROM category WHERE id = ?", attempt.category)
+            .fetch_one(dosb_db).await {
+                Ok(r) => r.category_group_id,
+                Err(e) => return Err(InternalServerf!("Error while fetching the group of the category {} ({})", attempt.category, e))
+        };
+        let stufe = evaluator.get_medal_for_attempt(age, gender, &attempt).await?;
+        match gruppen.get(&gruppe) {
+            Some(g) if g.stufe as u8 >= stufe as u8 => (),
+            _ => {
+                gruppen.insert(gruppe, GruppenErgebnis { attempt, stufe });
+            }
+        }
+    }
+    Ok(gruppen)
+}
+
+/// the names of the four groups, `Gruppe <id>` if the DOSB database has none
+pub async fn get_dosb_gruppen(dosb_db: &SqlitePool) -> Result<Vec<DOSBGruppe>, HttpResponse> {
+    let names = match sqlx::query!("SELECT id, name FROM category_group").fetch_all(dosb_db).await {
+        Ok(r) => r,
+        Err(e) => return Err(InternalServerf!("Error while fetching the groups of the dosb ({})", e))
+    };
+    let names: HashMap<i64, String> = names.into_iter().filter_map(|g| Some((g.id, g.name?))).collect();
+    Ok(GRUPPEN.iter()
+        .map(|id| DOSBGruppe {
+            id: *id,
+            name: names.get(id).cloned().unwrap_or(format!("Gruppe {}", id)),
+        })
+        .collect())
+}
+
+/// the list of all students of an event that got a DOSB abzeichen, sorted by class and name
+pub async fn get_dosb_meldeliste(role: &Role, db: &SqlitePool, dosb_db: &SqlitePool, bjs_db: &SqlitePool) -> Result<DOSBMeldeliste, HttpResponse> {
+    let mut rows = vec![];
+    for result in get_results(role, db, dosb_db, bjs_db).await? {
+        if result.dosb_abzeichen == DOSBAbzeichen::None {
+            continue;
+        }
+        let attempts = match sqlx::query_as!(Attempt, "SELECT kategorieId as category, wert as result FROM versuch WHERE schuelerId = ? AND isReal = true", result.id)
+            .fetch_all(db).await {
+                Ok(r) => r,
+                Err(e) => return Err(InternalServerf!("Error while fetching the attempts of {} ({})", result.id, e))
+        };
+        let gruppen = get_gruppen_ergebnisse(dosb_db, result.age, result.gesch, attempts).await?;
+        rows.push(DOSBMeldung { result, gruppen });
+    }
+
+    Ok(DOSBMeldeliste {
+        gruppen: get_dosb_gruppen(dosb_db).await?,
+        kategorien: get_kategorien(db).await?,
+        rows,
+    })
+}
+
+/// the german column headers of the list, three columns (disziplin, leistung, stufe) per group
+pub fn headers(liste: &DOSBMeldeliste) -> Vec<String> {
+    let mut headers: Vec<String> = ["Nr", "Nachname", "Vorname", "Geschlecht", "Alter", "Klasse"].iter().map(|h| h.to_string()).collect();
+    for g in &liste.gruppen {
+        headers.extend([format!("{} Disziplin", g.name), format!("{} Leistung", g.name), format!("{} Stufe", g.name)]);
+    }
+    headers.extend(["Punkte", "Abzeichen"].iter().map(|h| h.to_string()));
+    headers
+}
+
+/// the list as csv, separated by `;` so it opens in a german excel
+pub fn meldeliste_csv(liste: &DOSBMeldeliste) -> Result<String, HttpResponse> {
+    let kategorien: HashMap<i64, &ListenKategorie> = liste.kategorien.iter().map(|k| (k.id, k)).collect();
+    let mut writer = csv::WriterBuilder::new().delimiter(b';').from_writer(vec![]);
+    if let Err(e) = writer.write_record(headers(liste)) {
+        return Err(InternalServerf!("Error while writing the dosb list as csv ({})", e));
+    }
+    for row in &liste.rows {
+        let r = &row.result;
+        let mut record = vec![r.id.to_string(), r.lname.clone(), r.fname.clone(), r.gesch.to_string(), r.age.to_string(), r.klasse.clone()];
+        for g in &liste.gruppen {
+            match row.gruppen.get(&g.id) {
+                Some(e) => {
+                    let (name, wert) = match kategorien.get(&e.attempt.category) {
+                        Some(k) => (k.name.clone(), format!("{} {}", format_wert(e.attempt.result, k.digits_before, k.digits_after), k.einheit)),
+                        None => (e.attempt.category.to_string(), e.attempt.result.to_string()),
+                    };
+                    record.extend([name, wert, dosb_abzeichen_name(e.stufe).unwrap_or_default().to_string()]);
+                },
+                None => record.extend([String::new(), String::new(), String::new()]),
+            }
+        }
+        record.extend([r.dosb_punkte.to_string(), dosb_abzeichen_name(r.dosb_abzeichen).unwrap_or_default().to_string()]);
+        if let Err(e) = writer.write_record(record) {
+            return Err(InternalServerf!("Error while writing the dosb list as csv ({})", e));
+        }
+    }
+    match writer.into_inner() {
+        Ok(b) => Ok(String::from_utf8_lossy(&b).to_string()),
+        Err(e) => Err(InternalServerf!("Error while writing the dosb list as csv ({})", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    async fn meldeliste_of_schueler() {
+        let dosb_db = SqlitePool::connect("testData/2025dosb.db").await.unwrap();
+        // the same attempts as in dosb_eval::tests::schueler_5716_2025, the 50m twice
+        let attempts = vec![
+            Attempt { category: 4, result: 3.3 },
+            Attempt { category: 14, result: 215.0 },
+            Attempt { category: 1, result: 8.9 },
+            Attempt { category: 1, result: 7.7 },
+            Attempt { category: 6, result: 25.0 },
+        ];
+        let gruppen = get_gruppen_ergebnisse(&dosb_db, 11, 'm', attempts).await.unwrap();
+        assert_eq!(gruppen.len(), 4);
+        // the 50m are in group 4, the faster one counts
+        assert_eq!(gruppen[&4].attempt, Attempt { category: 1, result: 7.7 });
+        assert_eq!(gruppen[&4].stufe, DOSBAbzeichen::Gold);
+        assert_eq!(gruppen[&1].stufe, DOSBAbzeichen::Silber);
+
+        let liste = DOSBMeldeliste {
+            gruppen: get_dosb_gruppen(&dosb_db).await.unwrap(),
+            kategorien: vec![
+                ListenKategorie { id: 1, name: "50m Lauf".to_string(), einheit: "s".to_string(), digits_before: 1, digits_after: 1 },
+            ],
+            rows: vec![DOSBMeldung {
+                result: ResultRow {
+                    fname: "Max".to_string(),
+                    lname: "Muster".to_string(),
+                    klasse: "5a".to_string(),
+                    gesch: 'm',
+                    age: 11,
+                    dosb_punkte: 11,
+                    dosb_abzeichen: DOSBAbzeichen::Gold,
+                    ..ResultRow::test(5716)
+                },
+                gruppen: gruppen.into_iter().filter(|(id, _)| *id == 4).collect(),
+            }],
+        };
+        let csv = meldeliste_csv(&liste).unwrap();
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().starts_with("Nr;Nachname;Vorname;Geschlecht;Alter;Klasse;Gruppe 1 Disziplin;Gruppe 1 Leistung;Gruppe 1 Stufe;Gruppe 2 Disziplin"));
+        assert_eq!(lines.next().unwrap(), "5716;Muster;Max;m;11;5a;;;;;;;;;;50m Lauf;7,7 s;Gold;11;Gold");
+    }
+}
@@ -0,0 +1,169 @@
+//! Exports of the results of an event
+//!
+//! Everything in here is generated offline from the event database and the BJS and DOSB
+//! databases, e.g. for printing after the event.
+pub mod urkunden;
+pub mod listen;
+pub mod statistik;
+pub mod meldeliste;
+pub mod rangliste;
+pub mod klassenwertung;
+
+use sqlx::SqlitePool;
+use actix_web::HttpResponse;
+use serde::Serialize;
+use std::collections::HashMap;
+use printpdf::{BuiltinFont, Mm, PdfDocument};
+use crate::InternalServerf;
+use crate::bjs_eval::BJSAbzeichen;
+use crate::dosb_eval::DOSBAbzeichen;
+use crate::search::search_database;
+use crate::auth::roles::Role;
+
+/// the result of one student together with its name and class
+#[derive(Debug, Clone, Serialize)]
+pub struct ResultRow {
+    pub id: i64,
+    pub fname: String,
+    pub lname: String,
+    pub klasse: String,
+    pub gesch: char,
+    pub age: i64,
+    pub bjs_punkte: i64,
+    pub bjs_urkunde: BJSAbzeichen,
+    pub dosb_punkte: i64,
+    pub dosb_abzeichen: DOSBAbzeichen,
+}
+
+/**
+ * The results of all students of an event the role can see, sorted by class and name.
+ *
+ * Students without a class or name get an empty string, so they are sorted first.
+ */
+pub async fn get_results(role: &Role, db: &SqlitePool, dosb_db: &SqlitePool, bjs_db: &SqlitePool) -> Result<Vec<ResultRow>, HttpResponse> {
+    let schueler = match sqlx::query!("SELECT id, fName as fname, lName as lname, klasse, gesch, age FROM schueler")
+        .fetch_all(db).await {
+            Ok(r) => r,
+            Err(e) => return Err(InternalServerf!("Error while fetching the schueler ({})", e))
+    };
+    let schueler: HashMap<i64, _> = schueler.into_iter().filter_map(|s| s.id.map(|id| (id, s))).collect();
+
+    let mut rows: Vec<ResultRow> = search_database(role, db, dosb_db, bjs_db).await?
+        .into_iter()
+        .filter_map(|r| {
+            let s = schueler.get(&r.id)?;
+            Some(ResultRow {
+                id: r.id,
+                fname: s.fname.clone().unwrap_or_default(),
+                lname: s.lname.clone().unwrap_or_default(),
+                klasse: s.klasse.clone().unwrap_or_default(),
+                gesch: s.gesch.as_ref().and_then(|g| g.chars().next()).unwrap_or(' '),
+                age: s.age.unwrap_or_default(),
+                bjs_punkte: r.bjs_punkte,
+                bjs_urkunde: r.bjs_urkunde,
+                dosb_punkte: r.dosb_punkte,
+                dosb_abzeichen: r.dosb_abzeichen,
+            })
+        })
+        .collect();
+
+    rows.sort_by(|a, b| (&a.klasse, &a.lname, &a.fname, a.id).cmp(&(&b.klasse, &b.lname, &b.fname, b.id)));
+    Ok(rows)
+}
+
+#[cfg(test)]
+impl ResultRow {
+    /// a student of the 7a without name and results, tests set the fields they need with `..ResultRow::test(id)`
+    pub(crate) fn test(id: i64) -> ResultRow {
+        ResultRow {
+            id,
+            fname: String::new(),
+            lname: String::new(),
+            klasse: "7a".to_string(),
+            gesch: 'w',
+            age: 12,
+            bjs_punkte: 0,
+            bjs_urkunde: BJSAbzeichen::None,
+            dosb_punkte: 0,
+            dosb_abzeichen: DOSBAbzeichen::None,
+        }
+    }
+}
+
+/// the german name of a BJS urkunde, `None` if there is none
+pub fn bjs_urkunde_name(urkunde: BJSAbzeichen) -> Option<&'static str> {
+    match urkunde {
+        BJSAbzeichen::None => None,
+        BJSAbzeichen::Teilnehmer => Some("Teilnehmerurkunde"),
+        BJSAbzeichen::Sieger => Some("Siegerurkunde"),
+        BJSAbzeichen::Ehren => Some("Ehrenurkunde"),
+    }
+}
+
+/// the german name of a DOSB abzeichen, `None` if there is none
+pub fn dosb_abzeichen_name(abzeichen: DOSBAbzeichen) -> Option<&'static str> {
+    match abzeichen {
+        DOSBAbzeichen::None => None,
+        DOSBAbzeichen::Bronze => Some("Bronze"),
+        DOSBAbzeichen::Silber => Some("Silber"),
+        DOSBAbzeichen::Gold => Some("Gold"),
+    }
+}
+
+/**
+ * A simple table as PDF on A4 landscape, e.g. for forms that have to be sent somewhere.
+ *
+ * Headers can have more than one line, separated by `\n`. Long tables continue on the next page.
+ */
+pub(crate) fn table_pdf(title: &str, headers: &[&str], rows: &[Vec<String>]) -> Result<Vec<u8>, HttpResponse> {
+    const WIDTH: f32 = 297.0;
+    const HEIGHT: f32 = 210.0;
+    const MARGIN: f32 = 15.0;
+    const LINE: f32 = 5.0;
+
+    let (doc, page, layer) = PdfDocument::new(title, Mm(WIDTH), Mm(HEIGHT), "Tabelle");
+    let regular = match doc.add_builtin_font(BuiltinFont::Helvetica) {
+        Ok(f) => f,
+        Err(e) => return Err(InternalServerf!("Error while adding the font ({})", e))
+    };
+    let bold = match doc.add_builtin_font(BuiltinFont::HelveticaBold) {
+        Ok(f) => f,
+        Err(e) => return Err(InternalServerf!("Error while adding the font ({})", e))
+    };
+    let column = (WIDTH - 2.0 * MARGIN) / headers.len().max(1) as f32;
+
+    let mut layer = doc.get_page(page).get_layer(layer);
+    let mut y = HEIGHT - MARGIN;
+    layer.use_text(title, 14.0, Mm(MARGIN), Mm(y), &bold);
+    y -= 2.0 * LINE;
+
+    let header_lines = headers.iter().map(|h| h.lines().count()).max().unwrap_or(1);
+    let write_headers = |layer: &printpdf::PdfLayerReference, y: f32| {
+        for (i, h) in headers.iter().enumerate() {
+            for (j, line) in h.lines().enumerate() {
+                layer.use_text(line, 9.0, Mm(MARGIN + i as f32 * column), Mm(y - j as f32 * LINE), &bold);
+            }
+        }
+    };
+    write_headers(&layer, y);
+    y -= header_lines as f32 * LINE + 1.0;
+
+    for row in rows {
+        if y < MARGIN {
+            let (page, new_layer) = doc.add_page(Mm(WIDTH), Mm(HEIGHT), "Tabelle");
+            layer = doc.get_page(page).get_layer(new_layer);
+            y = HEIGHT - MARGIN;
+            write_headers(&layer, y);
+            y -= header_lines as f32 * LINE + 1.0;
+        }
+        for (i, cell) in row.iter().enumerate() {
+            layer.use_text(cell.as_str(), 9.0, Mm(MARGIN + i as f32 * column), Mm(y), &regular);
+        }
+        y -= LINE;
+    }
+
+    match doc.save_to_bytes() {
+        Ok(b) => Ok(b),
+        Err(e) => Err(InternalServerf!("Error while writing the pdf ({})", e))
+    }
+}
@@ -0,0 +1,216 @@
+//! Rankings of the students, e.g. for the best runners and jumpers of a year
+//!
+//! A kategorie is ranked by the best attempt of each student. If a bigger or smaller result is
+//! better comes from the BJS database (`running`), or from the DOSB database if the kategorie is
+//! not part of the BJS (bigger is better if gold is above bronze). The BJS ranking uses the points.
+//!
+//! Equal results get the same place and the following places are skipped (1, 2, 2, 4).
+use actix_web::HttpResponse;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use crate::{InternalServerf, NotFoundf};
+use crate::bjs_eval::BJSAbzeichen;
+use super::{ResultRow, get_results};
+use crate::auth::roles::Role;
+
+/// the students to rank, `None` means all
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RanglistenFilter {
+    pub age: Option<i64>,
+    pub gesch: Option<char>,
+    pub klasse: Option<String>,
+}
+
+impl RanglistenFilter {
+    fn matches(&self, age: i64, gesch: char, klasse: &str) -> bool {
+        self.age.is_none_or(|a| a == age)
+            && self.gesch.is_none_or(|g| g == gesch)
+            && self.klasse.as_ref().is_none_or(|k| k == klasse)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RanglistenEintrag {
+    pub platz: u32,
+    pub schueler_id: i64,
+    pub fname: String,
+    pub lname: String,
+    pub klasse: String,
+    pub gesch: char,
+    pub age: i64,
+    /// the best result in the kategorie or the BJS points
+    pub wert: f64,
+}
+
+/// true if a bigger result is better in the kategorie, e.g. for throwing but not for running
+pub async fn bigger_is_better(kategorie: i64, dosb_db: &SqlitePool, bjs_db: &SqlitePool) -> Result<bool, HttpResponse> {
+    match sqlx::query!("SELECT running FROM category WHERE id = ?", kategorie).fetch_optional(bjs_db).await {
+        Ok(Some(r)) => return Ok(!r.running),
+        Ok(None) => (),
+        Err(e) => return Err(InternalServerf!("Error while fetching the bjs category {} ({})", kategorie, e))
+    };
+    match sqlx::query!("SELECT gold, bronze FROM mand_category WHERE category_id = ? LIMIT 1", kategorie).fetch_optional(dosb_db).await {
+        Ok(Some(r)) => Ok(r.gold > r.bronze),
+        Ok(None) => Err(NotFoundf!("The category {} is neither part of the bjs nor of the dosb", kategorie)),
+        Err(e) => Err(InternalServerf!("Error while fetching the dosb category {} ({})", kategorie, e))
+    }
+}
+
+/// the ranking of a kategorie by the best attempt of each student
+pub async fn get_kategorie_rangliste(
+    kategorie: i64,
+    filter: &RanglistenFilter,
+    role: &Role,
+    db: &SqlitePool,
+    dosb_db: &SqlitePool,
+    bjs_db: &SqlitePool,
+) -> Result<Vec<RanglistenEintrag>, HttpResponse> {
+    let bigger = bigger_is_better(kategorie, dosb_db, bjs_db).await?;
+    let versuche = match sqlx::query!(r#"
+        SELECT schueler.id as "id!", fName as fname, lName as lname, klasse, gesch, age, wert FROM versuch
+        INNER JOIN schueler ON schueler.id = versuch.schuelerId
+        WHERE kategorieId = ? AND isReal = true
+    "#, kategorie).fetch_all(db).await {
+        Ok(r) => r,
+        Err(e) => return Err(InternalServerf!("Error while fetching the attempts of the category {} ({})", kategorie, e))
+    };
+
+    let mut best: HashMap<i64, RanglistenEintrag> = HashMap::new();
+    for v in versuche {
+        let gesch = v.gesch.as_ref().and_then(|g| g.chars().next()).unwrap_or(' ');
+        if !role.can_see_klasse(v.klasse.as_deref()) {
+            continue;
+        }
+        let klasse = v.klasse.unwrap_or_default();
+        let age = v.age.unwrap_or_default();
+        if !filter.matches(age, gesch, &klasse) {
+            continue;
+        }
+        match best.get_mut(&v.id) {
+            Some(e) => {
+                if (bigger && v.wert > e.wert) || (!bigger && v.wert < e.wert) {
+                    e.wert = v.wert;
+                }
+            },
+            None => {
+                best.insert(v.id, RanglistenEintrag {
+                    platz: 0,
+                    schueler_id: v.id,
+                    fname: v.fname.unwrap_or_default(),
+                    lname: v.lname.unwrap_or_default(),
+                    klasse,
+                    gesch,
+                    age,
+                    wert: v.wert,
+                });
+            }
+        }
+    }
+    Ok(platzieren(best.into_values().collect(), bigger))
+}
+
+/// the ranking by BJS points, students without a urkunde are left out
+pub async fn get_bjs_rangliste(filter: &RanglistenFilter, role: &Role, db: &SqlitePool, dosb_db: &SqlitePool, bjs_db: &SqlitePool) -> Result<Vec<RanglistenEintrag>, HttpResponse> {
+    let results = get_results(role, db, dosb_db, bjs_db).await?;
+    Ok(bjs_rangliste(results, filter))
+}
+
+fn bjs_rangliste(results: Vec<ResultRow>, filter: &RanglistenFilter) -> Vec<RanglistenEintrag> {
+    let eintraege = results.into_iter()
+        .filter(|r| r.bjs_urkunde != BJSAbzeichen::None && filter.matches(r.age, r.gesch, &r.klasse))
+        .map(|r| RanglistenEintrag {
+            platz: 0,
+            schueler_id: r.id,
+            fname: r.fname,
+            lname: r.lname,
+            klasse: r.klasse,
+            gesch: r.gesch,
+            age: r.age,
+            wert: r.bjs_punkte as f64,
+        })
+        .collect();
+    platzieren(eintraege, true)
+}
+
+/// sorts the entries and gives them their place, equal results get the same place
+fn platzieren(mut eintraege: Vec<RanglistenEintrag>, bigger_is_better: bool) -> Vec<RanglistenEintrag> {
+    eintraege.sort_by(|a, b| {
+        let order = if bigger_is_better { b.wert.total_cmp(&a.wert) } else { a.wert.total_cmp(&b.wert) };
+        order.then_with(|| (&a.lname, &a.fname, a.schueler_id).cmp(&(&b.lname, &b.fname, b.schueler_id)))
+    });
+    let werte: Vec<f64> = eintraege.iter().map(|e| e.wert).collect();
+    for (e, platz) in eintraege.iter_mut().zip(plaetze(&werte)) {
+        e.platz = platz;
+    }
+    eintraege
+}
+
+/// the places of sorted values, equal values get the same place (1, 2, 2, 4)
+pub(super) fn plaetze(werte: &[f64]) -> Vec<u32> {
+    let mut plaetze: Vec<u32> = Vec::with_capacity(werte.len());
+    for (i, wert) in werte.iter().enumerate() {
+        let platz = match plaetze.last() {
+            Some(last) if werte[i - 1] == *wert => *last,
+            _ => i as u32 + 1,
+        };
+        plaetze.push(platz);
+    }
+    plaetze
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(id: i64, gesch: char, bjs_punkte: i64, bjs_urkunde: BJSAbzeichen) -> ResultRow {
+        ResultRow { lname: format!("Schüler {}", id), gesch, bjs_punkte, bjs_urkunde, ..ResultRow::test(id) }
+    }
+
+    #[test]
+    fn rank_bjs_points() {
+        let results = vec![
+            row(1, 'm', 900, BJSAbzeichen::Sieger),
+            row(2, 'm', 1200, BJSAbzeichen::Ehren),
+            row(3, 'm', 900, BJSAbzeichen::Sieger),
+            row(4, 'm', 600, BJSAbzeichen::Teilnehmer),
+            row(5, 'w', 1300, BJSAbzeichen::Ehren),
+            row(6, 'm', 0, BJSAbzeichen::None),
+        ];
+        let filter = RanglistenFilter { gesch: Some('m'), ..Default::default() };
+        let rangliste = bjs_rangliste(results, &filter);
+        let plaetze: Vec<(i64, u32)> = rangliste.iter().map(|e| (e.schueler_id, e.platz)).collect();
+        assert_eq!(plaetze, vec![(2, 1), (1, 2), (3, 2), (4, 4)]);
+    }
+
+    #[sqlx::test]
+    async fn rank_kategorie() {
+        let db = crate::manage::test_event_db(
+            &[(1, "A", "5a", 'm', 11), (2, "B", "5a", 'm', 11), (3, "C", "5b", 'w', 11), (4, "D", "5b", 'm', 11)],
+            &[
+                (1, 1, 8.1, true), (1, 1, 7.9, true),
+                (2, 1, 7.9, true), (3, 1, 7.5, true),
+                (4, 1, 7.0, false), (4, 1, 8.5, true),
+                (1, 4, 3.1, true), (2, 4, 3.4, true),
+            ],
+        ).await;
+        let dosb_db = SqlitePool::connect("testData/2025dosb.db").await.unwrap();
+        let bjs_db = SqlitePool::connect("testData/2025bjs.db").await.unwrap();
+
+        // 50m, smaller is better, the attempt that is not real does not count
+        let filter = RanglistenFilter { gesch: Some('m'), ..Default::default() };
+        let role = Role::test(None);
+        let rangliste = get_kategorie_rangliste(1, &filter, &role, &db, &dosb_db, &bjs_db).await.unwrap();
+        let plaetze: Vec<(i64, u32, f64)> = rangliste.iter().map(|e| (e.schueler_id, e.platz, e.wert)).collect();
+        assert_eq!(plaetze, vec![(1, 1, 7.9), (2, 1, 7.9), (4, 3, 8.5)]);
+
+        // a class teacher only sees the own class
+        let rangliste = get_kategorie_rangliste(1, &filter, &Role::test(Some("5b")), &db, &dosb_db, &bjs_db).await.unwrap();
+        let ids: Vec<i64> = rangliste.iter().map(|e| e.schueler_id).collect();
+        assert_eq!(ids, vec![4]);
+
+        // weitsprung, bigger is better
+        let rangliste = get_kategorie_rangliste(4, &RanglistenFilter::default(), &role, &db, &dosb_db, &bjs_db).await.unwrap();
+        assert_eq!(rangliste[0].schueler_id, 2);
+    }
+}
@@ -0,0 +1,177 @@
+//! Statistics of the BJS urkunden for the Auswertungsbogen
+//!
+//! Schools report to the Bundesjugendspiele how many Teilnehmer-, Sieger- and Ehrenurkunden they
+//! awarded, per age and gender. A student counts as participant as soon as `BJSEvaluator::get_medal`
+//! gives any urkunde, students without attempts are not counted.
+use actix_web::HttpResponse;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use crate::InternalServerf;
+use crate::bjs_eval::BJSAbzeichen;
+use super::{ResultRow, get_results, table_pdf};
+use crate::auth::roles::Role;
+
+/// how many urkunden of each kind were awarded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct UrkundenCount {
+    pub teilnehmer: u32,
+    pub sieger: u32,
+    pub ehren: u32,
+}
+
+impl UrkundenCount {
+    pub fn add(&mut self, urkunde: BJSAbzeichen) {
+        match urkunde {
+            BJSAbzeichen::None => (),
+            BJSAbzeichen::Teilnehmer => self.teilnehmer += 1,
+            BJSAbzeichen::Sieger => self.sieger += 1,
+            BJSAbzeichen::Ehren => self.ehren += 1,
+        }
+    }
+
+    /// the number of participants, everybody that got a urkunde
+    pub fn total(&self) -> u32 {
+        self.teilnehmer + self.sieger + self.ehren
+    }
+}
+
+/// one row of the Auswertungsbogen, girls and boys of one age
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct GeschlechtCount {
+    pub w: UrkundenCount,
+    pub m: UrkundenCount,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BJSStatistik {
+    /// by age, sorted
+    pub alter: BTreeMap<i64, GeschlechtCount>,
+    /// by class, sorted
+    pub klassen: BTreeMap<String, GeschlechtCount>,
+    pub gesamt: GeschlechtCount,
+}
+
+/// counts the BJS urkunden of the results. Students with an unknown gender are left out.
+pub fn bjs_statistik(results: &[ResultRow]) -> BJSStatistik {
+    let mut statistik = BJSStatistik::default();
+    for r in results {
+        if r.bjs_urkunde == BJSAbzeichen::None {
+            continue;
+        }
+        let add = |count: &mut GeschlechtCount| match r.gesch {
+            'w' => count.w.add(r.bjs_urkunde),
+            'm' => count.m.add(r.bjs_urkunde),
+            _ => (),
+        };
+        add(statistik.alter.entry(r.age).or_default());
+        add(statistik.klassen.entry(r.klasse.clone()).or_default());
+        add(&mut statistik.gesamt);
+    }
+    statistik
+}
+
+/// the BJS statistics of an event
+pub async fn get_bjs_statistik(role: &Role, db: &SqlitePool, dosb_db: &SqlitePool, bjs_db: &SqlitePool) -> Result<BJSStatistik, HttpResponse> {
+    Ok(bjs_statistik(&get_results(role, db, dosb_db, bjs_db).await?))
+}
+
+const HEADERS: [&str; 9] = [
+    "Alter",
+    "Teilnehmerinnen", "Teilnehmerurkunden w", "Siegerurkunden w", "Ehrenurkunden w",
+    "Teilnehmer", "Teilnehmerurkunden m", "Siegerurkunden m", "Ehrenurkunden m",
+];
+
+const PDF_HEADERS: [&str; 9] = [
+    "Alter",
+    "Mädchen\nTeilnehmerinnen", "Mädchen\nTeilnehmer-\nurkunden", "Mädchen\nSieger-\nurkunden", "Mädchen\nEhren-\nurkunden",
+    "Jungen\nTeilnehmer", "Jungen\nTeilnehmer-\nurkunden", "Jungen\nSieger-\nurkunden", "Jungen\nEhren-\nurkunden",
+];
+
+/// the rows of the Auswertungsbogen, one per age and the sum at the end
+fn rows(statistik: &BJSStatistik) -> Vec<Vec<String>> {
+    let mut rows: Vec<Vec<String>> = statistik.alter.iter().map(|(age, c)| count_row(age.to_string(), c)).collect();
+    rows.push(count_row("Summe".to_string(), &statistik.gesamt));
+    rows
+}
+
+fn count_row(label: String, count: &GeschlechtCount) -> Vec<String> {
+    let mut row = vec![label];
+    for u in [&count.w, &count.m] {
+        row.extend([u.total(), u.teilnehmer, u.sieger, u.ehren].iter().map(|n| n.to_string()));
+    }
+    row
+}
+
+/// the Auswertungsbogen as csv, separated by `;` so it opens in a german excel
+pub fn statistik_csv(statistik: &BJSStatistik) -> Result<String, HttpResponse> {
+    write_csv(&HEADERS, &rows(statistik))
+}
+
+/// the statistics by class as csv, the first column is the class instead of the age
+pub fn statistik_klassen_csv(statistik: &BJSStatistik) -> Result<String, HttpResponse> {
+    let mut headers = HEADERS;
+    headers[0] = "Klasse";
+    let mut rows: Vec<Vec<String>> = statistik.klassen.iter().map(|(klasse, c)| count_row(klasse.clone(), c)).collect();
+    rows.push(count_row("Summe".to_string(), &statistik.gesamt));
+    write_csv(&headers, &rows)
+}
+
+/// the Auswertungsbogen as PDF table to print and send
+pub fn statistik_pdf(statistik: &BJSStatistik, event_name: &str) -> Result<Vec<u8>, HttpResponse> {
+    table_pdf(&format!("Bundesjugendspiele Auswertung {}", event_name), &PDF_HEADERS, &rows(statistik))
+}
+
+fn write_csv(headers: &[&str], rows: &[Vec<String>]) -> Result<String, HttpResponse> {
+    let mut writer = csv::WriterBuilder::new().delimiter(b';').from_writer(vec![]);
+    if let Err(e) = writer.write_record(headers) {
+        return Err(InternalServerf!("Error while writing the statistics as csv ({})", e));
+    }
+    for row in rows {
+        if let Err(e) = writer.write_record(row) {
+            return Err(InternalServerf!("Error while writing the statistics as csv ({})", e));
+        }
+    }
+    match writer.into_inner() {
+        Ok(b) => Ok(String::from_utf8_lossy(&b).to_string()),
+        Err(e) => Err(InternalServerf!("Error while writing the statistics as csv ({})", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(gesch: char, age: i64, klasse: &str, bjs_urkunde: BJSAbzeichen) -> ResultRow {
+        ResultRow { klasse: klasse.to_string(), gesch, age, bjs_urkunde, ..ResultRow::test(1000) }
+    }
+
+    #[test]
+    fn count_urkunden() {
+        let results = vec![
+            row('w', 12, "7a", BJSAbzeichen::Ehren),
+            row('w', 12, "7a", BJSAbzeichen::Sieger),
+            row('m', 12, "7b", BJSAbzeichen::Teilnehmer),
+            row('m', 13, "7b", BJSAbzeichen::Sieger),
+            row('m', 13, "7b", BJSAbzeichen::None),
+        ];
+        let statistik = bjs_statistik(&results);
+        assert_eq!(statistik.alter[&12].w, UrkundenCount { teilnehmer: 0, sieger: 1, ehren: 1 });
+        assert_eq!(statistik.alter[&13].m.total(), 1);
+        assert_eq!(statistik.klassen["7b"].m, UrkundenCount { teilnehmer: 1, sieger: 1, ehren: 0 });
+        assert_eq!(statistik.gesamt.w.total() + statistik.gesamt.m.total(), 4);
+
+        let csv = statistik_csv(&statistik).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next().unwrap(), HEADERS.join(";"));
+        assert_eq!(lines.next().unwrap(), "12;2;0;1;1;1;1;0;0");
+        assert_eq!(lines.next().unwrap(), "13;0;0;0;0;1;0;1;0");
+        assert_eq!(lines.next().unwrap(), "Summe;2;0;1;1;2;1;1;0");
+
+        let klassen = statistik_klassen_csv(&statistik).unwrap();
+        assert_eq!(klassen.lines().nth(2).unwrap(), "7b;0;0;0;0;2;1;1;0");
+        assert_eq!(klassen.lines().last().unwrap(), "Summe;2;0;1;1;2;1;1;0");
+
+        assert!(statistik_pdf(&statistik, "Sportfest").unwrap().starts_with(b"%PDF"));
+    }
+}
@@ -0,0 +1,251 @@
+//! Urkunden as PDF
+//!
+//! The urkunden are printed onto the pre-printed BJS and DOSB forms, so only the texts are
+//! rendered. Where they go is set in a template (`Config::urkunden_template`), a toml file with
+//! one layout for BJS and one for DOSB. All sizes are in mm, `y` counts from the bottom of the page.
+//! ```toml
+//! [bjs]
+//! width = 210.0
+//! height = 297.0
+//! fields = [
+//!     { text = "{vorname} {nachname}", x = 40.0, y = 150.0, size = 28.0, bold = true },
+//!     { text = "Klasse {klasse}", x = 40.0, y = 138.0 },
+//! ]
+//! ```
+//! The texts can contain `{vorname}`, `{nachname}`, `{klasse}`, `{punkte}`, `{urkunde}`,
+//! `{dosb_punkte}`, `{abzeichen}` and `{event}`.
+use actix_web::HttpResponse;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::path::Path;
+use printpdf::{BuiltinFont, Mm, PdfDocument};
+use crate::{InternalServerf, NotFound};
+use crate::config::Config;
+use super::{ResultRow, get_results, bjs_urkunde_name, dosb_abzeichen_name};
+use crate::auth::roles::Role;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum UrkundenArt {
+    BJS,
+    DOSB,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UrkundenTemplate {
+    pub bjs: UrkundenLayout,
+    pub dosb: UrkundenLayout,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UrkundenLayout {
+    pub width: f32,
+    pub height: f32,
+    pub fields: Vec<TemplateField>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TemplateField {
+    pub text: String,
+    pub x: f32,
+    pub y: f32,
+    #[serde(default = "default_size")]
+    pub size: f32,
+    #[serde(default)]
+    pub bold: bool,
+}
+
+fn default_size() -> f32 {
+    14.0
+}
+
+impl Default for UrkundenTemplate {
+    fn default() -> Self {
+        UrkundenTemplate {
+            bjs: UrkundenLayout::default(),
+            dosb: UrkundenLayout {
+                fields: vec![
+                    field("{vorname} {nachname}", 40.0, 150.0, 28.0, true),
+                    field("Klasse {klasse}", 40.0, 138.0, 14.0, false),
+                    field("Deutsches Sportabzeichen in {abzeichen}", 40.0, 120.0, 18.0, true),
+                    field("{event}", 40.0, 108.0, 14.0, false),
+                ],
+                ..UrkundenLayout::default()
+            },
+        }
+    }
+}
+
+impl Default for UrkundenLayout {
+    fn default() -> Self {
+        // A4 portrait
+        UrkundenLayout {
+            width: 210.0,
+            height: 297.0,
+            fields: vec![
+                field("{vorname} {nachname}", 40.0, 150.0, 28.0, true),
+                field("Klasse {klasse}", 40.0, 138.0, 14.0, false),
+                field("{urkunde} mit {punkte} Punkten", 40.0, 120.0, 18.0, true),
+                field("{event}", 40.0, 108.0, 14.0, false),
+            ],
+        }
+    }
+}
+
+impl UrkundenTemplate {
+    /// loads the template from a toml file
+    pub fn from_file(path: &Path) -> Result<UrkundenTemplate, String> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => return Err(format!("Couldnt read urkunden template {}: {}", path.display(), e))
+        };
+        match toml::from_str(&content) {
+            Ok(t) => Ok(t),
+            Err(e) => Err(format!("Couldnt parse urkunden template {}: {}", path.display(), e))
+        }
+    }
+
+    /// the template of the config, or the default one if none is set
+    pub fn from_config(config: &Config) -> Result<UrkundenTemplate, HttpResponse> {
+        match &config.urkunden_template {
+            Some(path) => match UrkundenTemplate::from_file(path) {
+                Ok(t) => Ok(t),
+                Err(e) => Err(InternalServerf!("{}", e))
+            },
+            None => Ok(UrkundenTemplate::default())
+        }
+    }
+
+    pub fn layout(&self, art: UrkundenArt) -> &UrkundenLayout {
+        match art {
+            UrkundenArt::BJS => &self.bjs,
+            UrkundenArt::DOSB => &self.dosb,
+        }
+    }
+}
+
+/**
+ * Renders one page per student that got a urkunde of the given art, in the order of `results`.
+ *
+ * Students without a urkunde are skipped. If nobody got one there is nothing to print and
+ * NotFound is returned.
+ */
+pub fn render_urkunden(template: &UrkundenTemplate, art: UrkundenArt, event_name: &str, results: &[ResultRow]) -> Result<Vec<u8>, HttpResponse> {
+    let layout = template.layout(art);
+    let pages: Vec<&ResultRow> = results.iter().filter(|r| has_urkunde(art, r)).collect();
+    if pages.is_empty() {
+        return Err(NotFound!("Nobody got a urkunde"));
+    }
+
+    let (doc, first_page, first_layer) = PdfDocument::new(format!("Urkunden {}", event_name), Mm(layout.width), Mm(layout.height), "Urkunde");
+    let regular = match doc.add_builtin_font(BuiltinFont::Helvetica) {
+        Ok(f) => f,
+        Err(e) => return Err(InternalServerf!("Error while adding the font ({})", e))
+    };
+    let bold = match doc.add_builtin_font(BuiltinFont::HelveticaBold) {
+        Ok(f) => f,
+        Err(e) => return Err(InternalServerf!("Error while adding the font ({})", e))
+    };
+
+    for (i, row) in pages.into_iter().enumerate() {
+        let layer = if i == 0 {
+            doc.get_page(first_page).get_layer(first_layer)
+        } else {
+            let (page, layer) = doc.add_page(Mm(layout.width), Mm(layout.height), "Urkunde");
+            doc.get_page(page).get_layer(layer)
+        };
+        for f in &layout.fields {
+            let font = if f.bold { &bold } else { &regular };
+            layer.use_text(fill_in(&f.text, row, event_name), f.size, Mm(f.x), Mm(f.y), font);
+        }
+    }
+
+    match doc.save_to_bytes() {
+        Ok(b) => Ok(b),
+        Err(e) => Err(InternalServerf!("Error while writing the urkunden ({})", e))
+    }
+}
+
+/**
+ * Creates the urkunden of an event as one PDF, sorted by class and name so they can be printed
+ * at once. With `schueler_id` only the urkunde of that student is created. Students of classes
+ * the role can't see are left out.
+ */
+#[allow(clippy::too_many_arguments)]
+pub async fn create_urkunden(
+    config: &Config,
+    art: UrkundenArt,
+    event_name: &str,
+    schueler_id: Option<i64>,
+    role: &Role,
+    db: &SqlitePool,
+    dosb_db: &SqlitePool,
+    bjs_db: &SqlitePool,
+) -> Result<Vec<u8>, HttpResponse> {
+    let template = UrkundenTemplate::from_config(config)?;
+    let mut results = get_results(role, db, dosb_db, bjs_db).await?;
+    if let Some(id) = schueler_id {
+        results.retain(|r| r.id == id);
+    }
+    render_urkunden(&template, art, event_name, &results)
+}
+
+fn has_urkunde(art: UrkundenArt, row: &ResultRow) -> bool {
+    match art {
+        UrkundenArt::BJS => bjs_urkunde_name(row.bjs_urkunde).is_some(),
+        UrkundenArt::DOSB => dosb_abzeichen_name(row.dosb_abzeichen).is_some(),
+    }
+}
+
+fn fill_in(text: &str, row: &ResultRow, event_name: &str) -> String {
+    text.replace("{vorname}", &row.fname)
+        .replace("{nachname}", &row.lname)
+        .replace("{klasse}", &row.klasse)
+        .replace("{punkte}", &row.bjs_punkte.to_string())
+        .replace("{urkunde}", bjs_urkunde_name(row.bjs_urkunde).unwrap_or_default())
+        .replace("{dosb_punkte}", &row.dosb_punkte.to_string())
+        .replace("{abzeichen}", dosb_abzeichen_name(row.dosb_abzeichen).unwrap_or_default())
+        .replace("{event}", event_name)
+}
+
+fn field(text: &str, x: f32, y: f32, size: f32, bold: bool) -> TemplateField {
+    TemplateField { text: text.to_string(), x, y, size, bold }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bjs_eval::BJSAbzeichen;
+
+    fn row(id: i64, bjs_urkunde: BJSAbzeichen) -> ResultRow {
+        ResultRow {
+            fname: "Jürgen".to_string(),
+            lname: "Größe".to_string(),
+            gesch: 'm',
+            bjs_punkte: 1234,
+            bjs_urkunde,
+            ..ResultRow::test(id)
+        }
+    }
+
+    #[test]
+    fn render_bjs_urkunden() {
+        let template: UrkundenTemplate = toml::from_str(r#"
+            [bjs]
+            width = 297.0
+            height = 210.0
+            fields = [{ text = "{vorname} {nachname}, {urkunde}", x = 20.0, y = 100.0, bold = true }]
+        "#).unwrap();
+        assert_eq!(template.bjs.fields[0].size, 14.0);
+        // the dosb layout was not set
+        assert_eq!(template.dosb, UrkundenTemplate::default().dosb);
+
+        let results = vec![row(1, BJSAbzeichen::Ehren), row(2, BJSAbzeichen::None)];
+        assert_eq!(fill_in(&template.bjs.fields[0].text, &results[0], "Sportfest"), "Jürgen Größe, Ehrenurkunde");
+
+        let pdf = render_urkunden(&template, UrkundenArt::BJS, "Sportfest", &results).unwrap();
+        assert!(pdf.starts_with(b"%PDF"));
+        assert!(render_urkunden(&template, UrkundenArt::DOSB, "Sportfest", &results).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+//! Live feed of the changes of an event
+//!
+//! Result boards and the dashboard don't have to poll: `interact::add_versuch`, `set_is_real`
+//! and `upload_schueler` publish a `FeedEvent` to the `EventFeed` of the app state, and
+//! `sse` streams them to the browser as server-sent events. Every message has the id of the
+//! event it belongs to (`None` if the user was not bound to an event, e.g. an admin). Only
+//! admins with the role `admin` may listen to other or all events, everybody else only gets the
+//! messages of the own event. The messages don't know the class of a student, so roles that are
+//! limited to one class don't get the messages with the ids of students.
+//!
+//! Slow clients that miss messages get a `lagged` event with the number of missed messages,
+//! they should fetch everything again.
+use actix_web::HttpResponse;
+use actix_web::web::Bytes;
+use futures::Stream;
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use crate::Forbidden;
+use crate::auth::AuthUser;
+use crate::auth::roles::Role;
+
+/// how many messages are kept for slow clients
+const CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FeedEvent {
+    VersuchAdded { versuch_id: i32, schueler_id: i32, kategorie_id: i32, wert: f32 },
+    /// a attempt was invalidated or made valid again with `set_is_real`
+    VersuchChanged { versuch_id: i32, is_real: bool },
+    SchuelerUploaded { ids: Vec<i64> },
+}
+
+impl FeedEvent {
+    /// true if the message has the ids of students, which may be of other classes
+    fn has_schueler_ids(&self) -> bool {
+        matches!(self, FeedEvent::VersuchAdded { .. } | FeedEvent::SchuelerUploaded { .. })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FeedMessage {
+    pub event_id: Option<String>,
+    #[serde(flatten)]
+    pub event: FeedEvent,
+}
+
+/// the broadcast channel of the feed, cloning it gives a handle to the same channel
+#[derive(Debug, Clone)]
+pub struct EventFeed {
+    sender: broadcast::Sender<FeedMessage>,
+}
+
+impl Default for EventFeed {
+    fn default() -> Self {
+        EventFeed::new(CAPACITY)
+    }
+}
+
+impl EventFeed {
+    pub fn new(capacity: usize) -> EventFeed {
+        let (sender, _) = broadcast::channel(capacity);
+        EventFeed { sender }
+    }
+
+    /// sends a event to everybody that listens. Nobody listening is not a error.
+    pub fn publish(&self, event_id: Option<&str>, event: FeedEvent) {
+        let _ = self.sender.send(FeedMessage { event_id: event_id.map(|e| e.to_string()), event });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<FeedMessage> {
+        self.sender.subscribe()
+    }
+}
+
+/**
+ * A server-sent events response with the messages of the feed. With `event_id` only the
+ * messages of that event are sent. Users that aren't admins always get the messages of their
+ * own event, whatever `event_id` is. Roles with a `klasse` only get the messages without students.
+ */
+pub fn sse(feed: &EventFeed, user: &AuthUser, role: &Role, event_id: Option<String>) -> HttpResponse {
+    let event_id = match feed_filter(user, role, event_id) {
+        Ok(e) => e,
+        Err(e) => return e
+    };
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(sse_stream(feed.subscribe(), event_id, role.klasse.is_none()))
+}
+
+/// the event a user may listen to, `None` means all events
+fn feed_filter(user: &AuthUser, role: &Role, event_id: Option<String>) -> Result<Option<String>, HttpResponse> {
+    if role.is_admin() && matches!(user, AuthUser::Admin { .. } | AuthUser::AdminWithEvent { .. }) {
+        return Ok(event_id);
+    }
+    match user.event_id() {
+        Some(e) => Ok(Some(e.to_string())),
+        None => Err(Forbidden!("Only admins can listen to the feed without an event"))
+    }
+}
+
+/// `with_schueler` is false for roles that may not see every student, see `FeedEvent::has_schueler_ids`
+fn sse_stream(receiver: broadcast::Receiver<FeedMessage>, event_id: Option<String>, with_schueler: bool) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    futures::stream::unfold((receiver, event_id), move |(mut receiver, event_id)| async move {
+        loop {
+            let data = match receiver.recv().await {
+                Ok(m) => {
+                    if event_id.is_some() && m.event_id != event_id {
+                        continue;
+                    }
+                    if !with_schueler && m.event.has_schueler_ids() {
+                        continue;
+                    }
+                    format!("data: {}\n\n", serde_json::to_string(&m).unwrap_or_default())
+                },
+                Err(RecvError::Lagged(n)) => format!("event: lagged\ndata: {}\n\n", n),
+                Err(RecvError::Closed) => return None,
+            };
+            return Some((Ok(Bytes::from(data)), (receiver, event_id)));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[actix_web::test]
+    async fn stream_events_of_one_event() {
+        let feed = EventFeed::new(2);
+        let stream = sse_stream(feed.subscribe(), Some("sportfest".to_string()), true);
+        futures::pin_mut!(stream);
+
+        feed.publish(Some("other"), FeedEvent::VersuchChanged { versuch_id: 1, is_real: false });
+        feed.publish(Some("sportfest"), FeedEvent::VersuchChanged { versuch_id: 2, is_real: false });
+        let data = stream.next().await.unwrap().unwrap();
+        assert_eq!(data, Bytes::from(r#"data: {"event_id":"sportfest","type":"versuch_changed","versuch_id":2,"is_real":false}"#.to_string() + "\n\n"));
+
+        // only 2 messages are kept
+        for i in 0..3 {
+            feed.publish(Some("sportfest"), FeedEvent::SchuelerUploaded { ids: vec![i] });
+        }
+        assert_eq!(stream.next().await.unwrap().unwrap(), Bytes::from("event: lagged\ndata: 1\n\n"));
+        assert!(stream.next().await.unwrap().unwrap().starts_with(b"data: "));
+    }
+
+    #[actix_web::test]
+    async fn no_students_for_class_teachers() {
+        let feed = EventFeed::new(4);
+        let stream = sse_stream(feed.subscribe(), Some("sportfest".to_string()), false);
+        futures::pin_mut!(stream);
+
+        feed.publish(Some("sportfest"), FeedEvent::VersuchAdded { versuch_id: 1, schueler_id: 1234, kategorie_id: 1, wert: 7.5 });
+        feed.publish(Some("sportfest"), FeedEvent::SchuelerUploaded { ids: vec![1234] });
+        feed.publish(Some("sportfest"), FeedEvent::VersuchChanged { versuch_id: 1, is_real: false });
+        let data = stream.next().await.unwrap().unwrap();
+        assert!(data.starts_with(br#"data: {"event_id":"sportfest","type":"versuch_changed""#));
+    }
+
+    #[test]
+    fn only_admins_choose_the_event() {
+        let admin = AuthUser::Admin { api_key: String::new(), username: "admin".to_string() };
+        assert_eq!(feed_filter(&admin, &Role::test(None), None).unwrap(), None);
+        assert_eq!(feed_filter(&admin, &Role::test(None), Some("other".to_string())).unwrap(), Some("other".to_string()));
+
+        let helper = AuthUser::TmpUser { id: "ABCDEF".to_string(), api_key: String::new(), event_id: "sportfest".to_string() };
+        let role = Role { name: "helper".to_string(), permissions: vec![], klasse: None };
+        assert_eq!(feed_filter(&helper, &role, None).unwrap(), Some("sportfest".to_string()));
+        assert_eq!(feed_filter(&helper, &role, Some("other".to_string())).unwrap(), Some("sportfest".to_string()));
+
+        let teacher = AuthUser::Admin { api_key: String::new(), username: "lehrer".to_string() };
+        let role = Role { name: "class_teacher".to_string(), permissions: vec![], klasse: Some("7a".to_string()) };
+        assert!(feed_filter(&teacher, &role, Some("other".to_string())).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//! Progress of a running event for the dashboard of the organisers
+//!
+//! The dashboard polls this every few seconds, so nothing is evaluated per student. There are
+//! three queries on the event db and one on each of the BJS and DOSB databases. A group counts
+//! as finished like in `get_missing_categorys`: one of the needed categories of the group is done.
+//!
+//! Class teachers only get the progress of their class. The counts of the kategorien are always
+//! those of the whole event, they show how busy the stations are.
+use actix_web::HttpResponse;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::InternalServerf;
+use crate::auth::roles::Role;
+use crate::model::Category;
+
+/// a station is busy if it recorded an attempt in the last seconds
+pub const AKTIV_ZEIT: i64 = 300;
+
+/// the ids of the category groups, in the BJS and the DOSB
+const GRUPPEN: [i64; 4] = [1, 2, 3, 4];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct GruppenFortschritt {
+    pub schueler: u32,
+    /// students with at least one attempt
+    pub begonnen: u32,
+    /// how many students finished each BJS group, by the id of the group
+    pub bjs_gruppen: BTreeMap<i64, u32>,
+    /// students with 3 BJS groups, they can get a sieger- or ehrenurkunde
+    pub bjs_fertig: u32,
+    /// how many students finished each DOSB group, by the id of the group
+    pub dosb_gruppen: BTreeMap<i64, u32>,
+    /// students with all 4 DOSB groups
+    pub dosb_fertig: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KategorieFortschritt {
+    pub kategorie: i64,
+    /// students with at least one attempt in the category
+    pub schueler: i64,
+    pub versuche: i64,
+    /// attempts of the last `AKTIV_ZEIT` seconds
+    pub aktiv: i64,
+    pub letzter_versuch: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Fortschritt {
+    pub zeit: i64,
+    pub kategorien: Vec<KategorieFortschritt>,
+    pub klassen: BTreeMap<String, GruppenFortschritt>,
+    pub alter: BTreeMap<i64, GruppenFortschritt>,
+    pub gesamt: GruppenFortschritt,
+}
+
+/// the progress of the students of an event the role can see, see the module docs
+pub async fn get_fortschritt(role: &Role, db: &SqlitePool, dosb_db: &SqlitePool, bjs_db: &SqlitePool) -> Result<Fortschritt, HttpResponse> {
+    let zeit = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let kategorien = get_kategorien_fortschritt(db, zeit).await?;
+
+    let schueler = match sqlx::query!("SELECT id, klasse, gesch, age FROM schueler").fetch_all(db).await {
+        Ok(r) => r,
+        Err(e) => return Err(InternalServerf!("Error while fetching the schueler ({})", e))
+    };
+    let done = match sqlx::query!("SELECT DISTINCT schuelerId as schueler_id, kategorieId as kategorie_id FROM versuch WHERE isReal = true")
+        .fetch_all(db).await {
+            Ok(r) => r,
+            Err(e) => return Err(InternalServerf!("Error while fetching the attempts ({})", e))
+    };
+    let mut done_by_schueler: HashMap<i64, HashSet<i64>> = HashMap::new();
+    for d in done {
+        done_by_schueler.entry(d.schueler_id).or_default().insert(d.kategorie_id);
+    }
+
+    let bjs_needed = get_needed(bjs_db).await?;
+    let dosb_needed = get_needed(dosb_db).await?;
+
+    let mut fortschritt = Fortschritt { zeit, kategorien, ..Default::default() };
+    let no_attempts = HashSet::new();
+    for s in schueler {
+        let id = match s.id {
+            Some(id) if role.can_see_klasse(s.klasse.as_deref()) => id,
+            _ => continue
+        };
+        let age = s.age.unwrap_or_default();
+        let gender = s.gesch.as_ref().and_then(|g| g.chars().next()).unwrap_or(' ');
+        let done = done_by_schueler.get(&id).unwrap_or(&no_attempts);
+        let bjs = done_groups(bjs_needed.get(&(age, gender)), done);
+        let dosb = done_groups(dosb_needed.get(&(age, gender)), done);
+
+        let add = |f: &mut GruppenFortschritt| {
+            f.schueler += 1;
+            if done.is_empty() {
+                return;
+            }
+            f.begonnen += 1;
+            for g in &bjs {
+                *f.bjs_gruppen.entry(*g).or_default() += 1;
+            }
+            for g in &dosb {
+                *f.dosb_gruppen.entry(*g).or_default() += 1;
+            }
+            if bjs.len() >= 3 {
+                f.bjs_fertig += 1;
+            }
+            if dosb.len() == GRUPPEN.len() {
+                f.dosb_fertig += 1;
+            }
+        };
+        add(fortschritt.klassen.entry(s.klasse.unwrap_or_default()).or_default());
+        add(fortschritt.alter.entry(age).or_default());
+        add(&mut fortschritt.gesamt);
+    }
+    Ok(fortschritt)
+}
+
+/// the attempts of each kategorie, `aktiv` counts those since `zeit - AKTIV_ZEIT`
+pub async fn get_kategorien_fortschritt(db: &SqlitePool, zeit: i64) -> Result<Vec<KategorieFortschritt>, HttpResponse> {
+    let aktiv_seit = zeit - AKTIV_ZEIT;
+    match sqlx::query_as!(KategorieFortschritt, r#"
+        SELECT kategorieId as "kategorie!", COUNT(DISTINCT schuelerId) as "schueler!: i64", COUNT(*) as "versuche!: i64",
+            IFNULL(SUM(mTime >= ?), 0) as "aktiv!: i64", MAX(mTime) as "letzter_versuch?: i64"
+        FROM versuch WHERE isReal = true
+        GROUP BY kategorieId ORDER BY kategorieId
+    "#, aktiv_seit).fetch_all(db).await {
+        Ok(r) => Ok(r),
+        Err(e) => Err(InternalServerf!("Error while counting the attempts ({})", e))
+    }
+}
+
+/// the needed categories of all ages and genders of the BJS or the DOSB
+async fn get_needed(db: &SqlitePool) -> Result<HashMap<(i64, char), Vec<Category>>, HttpResponse> {
+    let rows = match sqlx::query!(r#"
+        SELECT age, gender, category_id, category_group_id FROM mand_category
+        INNER JOIN category ON category_id = category.id
+    "#).fetch_all(db).await {
+        Ok(r) => r,
+        Err(e) => return Err(InternalServerf!("Error while fetching the needed categories ({})", e))
+    };
+    let mut needed: HashMap<(i64, char), Vec<Category>> = HashMap::new();
+    for r in rows {
+        let gender = r.gender.chars().next().unwrap_or(' ');
+        needed.entry((r.age, gender)).or_default().push(Category { id: r.category_id, group_id: r.category_group_id });
+    }
+    Ok(needed)
+}
+
+/// the groups that are finished with the done categories, none if the age or gender is unknown
+fn done_groups(needed: Option<&Vec<Category>>, done: &HashSet<i64>) -> Vec<i64> {
+    let needed = match needed {
+        Some(n) => n,
+        None => return vec![]
+    };
+    GRUPPEN.iter()
+        .copied()
+        .filter(|g| {
+            let mut in_group = needed.iter().filter(|c| c.group_id == *g).peekable();
+            in_group.peek().is_none() || in_group.any(|c| done.contains(&c.id))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    async fn progress_of_event() {
+        let db = crate::manage::test_event_db(
+            &[(1, "A", "5a", 'm', 11), (2, "B", "5a", 'm', 11), (3, "C", "5b", 'm', 11)],
+            &[
+                (1, 1, 7.7, true), (1, 4, 3.3, true),
+                (1, 6, 25.0, true), (1, 14, 215.0, true),
+                (2, 1, 8.1, true), (2, 1, 7.9, true),
+                (3, 4, 3.0, false),
+            ],
+        ).await;
+        let dosb_db = SqlitePool::connect("testData/2025dosb.db").await.unwrap();
+        let bjs_db = SqlitePool::connect("testData/2025bjs.db").await.unwrap();
+
+        let fortschritt = get_fortschritt(&Role::test(None), &db, &dosb_db, &bjs_db).await.unwrap();
+        assert_eq!(fortschritt.kategorien[0], KategorieFortschritt { kategorie: 1, schueler: 2, versuche: 3, aktiv: 0, letzter_versuch: Some(0) });
+        // the attempt that is not real does not count
+        assert_eq!(fortschritt.kategorien.len(), 4);
+
+        let klasse = &fortschritt.klassen["5a"];
+        assert_eq!(klasse.schueler, 2);
+        assert_eq!(klasse.begonnen, 2);
+        assert_eq!(klasse.bjs_fertig, 1);
+        assert_eq!(klasse.dosb_fertig, 1);
+        assert_eq!(klasse.bjs_gruppen[&1], 2);
+        assert_eq!(fortschritt.klassen["5b"].begonnen, 0);
+        assert_eq!(fortschritt.gesamt.schueler, 3);
+        assert_eq!(fortschritt.alter[&11].bjs_fertig, 1);
+
+        // class teachers only see their class
+        let fortschritt = get_fortschritt(&Role::test(Some("5b")), &db, &dosb_db, &bjs_db).await.unwrap();
+        assert_eq!(fortschritt.klassen.keys().collect::<Vec<_>>(), vec!["5b"]);
+        assert_eq!(fortschritt.gesamt.schueler, 1);
+    }
+}
@@ -21,6 +21,7 @@
  * - Unauthorized
  * - NotFound
  * - Forbidden
+ * - TooManyRequests
  */
 #[macro_use]
 pub mod res {
@@ -60,6 +61,12 @@ pub mod res {
             HttpResponse::InternalServerError().json(serde_json::json!({"message": $message}))
         };
     }
+    #[macro_export]
+    macro_rules! TooManyRequests{
+        ($message:expr) => {
+            HttpResponse::TooManyRequests().json(serde_json::json!({"message": $message}))
+        };
+    }
     // format
     #[macro_export]
     macro_rules! Unauthorizedf {
@@ -97,4 +104,10 @@ pub mod res {
            HttpResponse::InternalServerError().json(serde_json::json!({"message": format!( $( $x, )* )}))
         };
     }
+    #[macro_export]
+    macro_rules! TooManyRequestsf {
+        ( $( $x:expr ),* ) => {
+            HttpResponse::TooManyRequests().json(serde_json::json!({"message": format!($($x,)*)}))
+        };
+    }
 }
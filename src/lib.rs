@@ -4,6 +4,12 @@ pub use emotion_lib_derive as macros;
 pub mod manage;
 pub mod search;
 pub mod auth;
+pub mod config;
+pub mod audit;
+pub mod export;
+pub mod fortschritt;
+pub mod feed;
+pub mod stationen;
 mod model;
  pub mod schema;
 #[macro_use]
@@ -26,12 +32,19 @@ pub mod interact {
     use crate::schema;
     use crate::model::Attempt;
     use crate::UploadSchuelerResult;
+    use crate::config::Config;
+    use crate::auth;
+    use crate::auth::AuthUser;
+    use crate::auth::roles::Role;
+    use crate::audit::{self, AuditAction};
+    use crate::feed::{EventFeed, FeedEvent};
     use crate::search::search_schema;
     use crate::search::result2extensive;
 
     use regex::Regex;
     use sqlx::SqlitePool;
     use std::string::String;
+    use std::collections::{HashMap, HashSet};
     use std::time::{SystemTime, UNIX_EPOCH};
     use actix_web::HttpResponse;
 
@@ -59,12 +72,27 @@ pub mod interact {
         Ok((schueler.age.unwrap(), schueler.gesch.unwrap().chars().nth(0).unwrap()))
     }
 
+    /// `Forbidden` if the role may not see the class of the student
+    async fn check_can_see(id: i64, role: &Role, db: &SqlitePool) -> Result<(), HttpResponse> {
+        let klasse = match sqlx::query!("SELECT klasse FROM schueler WHERE id = ?", id).fetch_one(db).await {
+            Ok(r) => r.klasse,
+            Err(sqlx::Error::RowNotFound) => return Err(NotFoundf!("The Student {} was not found in the Database", id)),
+            Err(e) => return Err(InternalServerf!("There was an Error gettin the student from the database: {}", e))
+        };
+        if !role.can_see_klasse(klasse.as_deref()) {
+            return Err(Forbiddenf!("The role {} may not see the student {}", role.name, id));
+        }
+        Ok(())
+    }
+
     pub async fn get_schueler(
         id: &i32,
+        role: &Role,
         db: &SqlitePool,
         dosb_db: &SqlitePool,
         bjs_db: &SqlitePool,
     ) -> Result<search_schema::SchuelerResultExtensive, HttpResponse> {
+        check_can_see(*id as i64, role, db).await?;
         let attempts = get_attempts(id.clone() as i64, db).await?;
         let (age, gender) = get_schueler_data(id.clone() as i64, db).await?;
         // now we calculate the medals
@@ -90,11 +118,94 @@ pub mod interact {
 
     }
 
+    /**
+     * The card of a student for the info point. For each group of the BJS and the DOSB it has the
+     * needed kategorien with the best result and the attempts left, and if the group is done. The
+     * current points, urkunde and abzeichen are calculated like in `get_schueler`.
+     */
+    pub async fn get_schueler_karte(
+        id: i32,
+        role: &Role,
+        db: &SqlitePool,
+        dosb_db: &SqlitePool,
+        bjs_db: &SqlitePool,
+    ) -> Result<schema::SchuelerKarte, HttpResponse> {
+        let schueler = match sqlx::query!("SELECT fName as fname, lName as lname, klasse FROM schueler WHERE id = ?", id).fetch_one(db).await {
+            Ok(r) => r,
+            Err(sqlx::Error::RowNotFound) => return Err(NotFoundf!("The Student {} was not found in the Database", id)),
+            Err(e) => return Err(InternalServerf!("There was an Error gettin the student from the database: {}", e))
+        };
+        if !role.can_see_klasse(schueler.klasse.as_deref()) {
+            return Err(Forbiddenf!("The role {} may not see the student {}", role.name, id));
+        }
+        let attempts = get_attempts(id as i64, db).await?;
+        let (age, gender) = get_schueler_data(id as i64, db).await?;
+        let kategorien = match sqlx::query!("SELECT id, name, einheit, maxVers as max_vers FROM kategorien").fetch_all(db).await {
+            Ok(r) => r.into_iter().map(|k| (k.id, (k.name.unwrap_or_default(), k.einheit.unwrap_or_default(), k.max_vers))).collect(),
+            Err(e) => return Err(InternalServerf!("Error while getting the categorys of the Event {}", e))
+        };
+
+        let bjs_evaluator = BJSEvaluator {
+            db: bjs_db
+        };
+        let dosb_evaluator = DOSBEvaluator {
+            db: dosb_db
+        };
+        let bjs_top = bjs_evaluator.get_top_attempts(age, gender, attempts.clone()).await?;
+        let dosb_top = dosb_evaluator.get_top_attempts(age, gender, attempts.clone()).await?;
+
+        Ok(schema::SchuelerKarte {
+            id: id as i64,
+            first_name: schueler.fname.unwrap_or_default(),
+            last_name: schueler.lname.unwrap_or_default(),
+            klasse: schueler.klasse.unwrap_or_default(),
+            age,
+            gesch: gender,
+            bjs_punkte: bjs_evaluator.calculate_points_sum(age, gender, attempts.clone()).await?,
+            bjs_urkunde: bjs_evaluator.get_medal(age, gender, attempts.clone()).await?,
+            bjs_gruppen: karten_gruppen(bjs_evaluator.get_needed_categorys(age, gender).await?, &bjs_top, &attempts, &kategorien),
+            dosb_punkte: dosb_evaluator.calculate_points(age, gender, attempts.clone()).await? as i64,
+            dosb_abzeichen: dosb_evaluator.get_medal(age, gender, attempts.clone()).await?,
+            dosb_gruppen: karten_gruppen(dosb_evaluator.get_needed_categorys(age, gender).await?, &dosb_top, &attempts, &kategorien),
+        })
+    }
+
+    /// the groups of the card, `kategorien` has the name, einheit and maxVers of each kategorie of the event
+    fn karten_gruppen(
+        needed: Vec<model::Category>,
+        top: &[Attempt],
+        attempts: &[Attempt],
+        kategorien: &HashMap<i64, (String, String, Option<i64>)>,
+    ) -> Vec<schema::KartenGruppe> {
+        (1..=4).map(|group_id| {
+            let in_group: Vec<&model::Category> = needed.iter().filter(|c| c.group_id == group_id).collect();
+            // like in get_missing_categorys a group without needed kategorien is done
+            let fertig = in_group.is_empty() || in_group.iter().any(|c| attempts.iter().any(|a| a.category == c.id));
+            let kategorien = in_group.iter()
+                .filter_map(|c| {
+                    let (name, einheit, max_vers) = kategorien.get(&c.id)?;
+                    let versuche = attempts.iter().filter(|a| a.category == c.id).count() as i64;
+                    Some(schema::KartenKategorie {
+                        id: c.id,
+                        name: name.clone(),
+                        einheit: einheit.clone(),
+                        bester_wert: top.iter().find(|a| a.category == c.id).map(|a| a.result),
+                        versuche,
+                        versuche_uebrig: max_vers.map(|m| (m - versuche).max(0)),
+                    })
+                })
+                .collect();
+            schema::KartenGruppe { id: group_id, fertig, kategorien }
+        }).collect()
+    }
+
     pub async fn get_dosb_task_for_schueler(
         id: i32,
+        role: &Role,
         db: &SqlitePool,
         dosb_db: &SqlitePool
     ) -> Result<Vec<Vec<i64>>, HttpResponse> {
+        check_can_see(id as i64, role, db).await?;
         let attempts = get_attempts(id.clone() as i64, db).await?;
         let (age, gender) = get_schueler_data(id.clone() as i64, db).await?;
         let event_categorys: Vec<i64> = match sqlx::query!("SELECT id FROM kategorien").fetch_all(db).await {
@@ -118,9 +229,11 @@ pub mod interact {
 
     pub async fn get_bjs_task_for_schueler(
         id: i32,
+        role: &Role,
         db: &SqlitePool,
         bjs_db: &SqlitePool,
     ) -> Result<Vec<Vec<i64>>, HttpResponse> {
+        check_can_see(id as i64, role, db).await?;
         let attempts = get_attempts(id.clone() as i64, db).await?;
         let (age, gender) = get_schueler_data(id.clone() as i64, db).await?;
         let event_categorys: Vec<i64> = match sqlx::query!("SELECT id FROM kategorien").fetch_all(db).await {
@@ -144,8 +257,20 @@ pub mod interact {
 
     pub async fn upload_schueler(
         schueler_list: Vec<schema::UploadSchueler>,
+        user: &AuthUser,
         db: &SqlitePool,
-    ) -> UploadSchuelerResult {
+        auth_db: &SqlitePool,
+        config: &Config,
+        feed: &EventFeed,
+    ) -> Result<UploadSchuelerResult, HttpResponse> {
+        let id_schema = get_id_schema(db, config).await?;
+        let mut taken_ids: HashSet<i64> = match sqlx::query!("SELECT id FROM schueler").fetch_all(db).await {
+            Ok(r) => r.into_iter().filter_map(|s| s.id).collect(),
+            Err(e) => return Err(InternalServerf!("Error while getting the ids of the students ({})", e))
+        };
+        // the next number that is tried when a id has to be assigned
+        let mut next_number = id_schema.min;
+
         let mut result = UploadSchuelerResult {
             valid: vec![],
             age_invalid: vec![],
@@ -160,7 +285,7 @@ pub mod interact {
             let age: i8;
             if schueler.age.is_some() && schueler.age.clone().unwrap() != -1 {
                 age = schueler.age.clone().unwrap();
-                if !(5..25).contains(&age) {
+                if !config.ages.contains(&age) {
                     result.age_invalid.push(schueler);
                     continue;
                 }
@@ -181,7 +306,7 @@ pub mod interact {
                     }
                 };
                 age = (now - b_day) as i8;
-                if !(5..25).contains(&age) {
+                if !config.ages.contains(&age) {
                     result.age_invalid.push(schueler);
                     continue;
                 }
@@ -190,39 +315,132 @@ pub mod interact {
                 continue;
             }
 
-            if !(1000..9999).contains(&schueler.id) {
-                result.id_invalid.push(schueler);
-                continue;
-            }
-
             if !['m', 'w'].contains(&schueler.gesch) {
                 result.gesch_invalid.push(schueler);
                 continue;
             }
 
-            let id = schueler.id.clone();
-            let gesch = schueler.gesch.clone().to_string();
+            let external_id = if id_schema.use_external_ids { schueler.external_id } else { None };
+            let id = match (schueler.id, external_id) {
+                (Some(id), _) => {
+                    if !id_schema.is_valid(id) {
+                        result.id_invalid.push(schueler);
+                        continue;
+                    }
+                    id
+                },
+                // the ids of the school administration are used as they are
+                (None, Some(id)) => id,
+                (None, None) => {
+                    // assign the next free id
+                    while next_number < id_schema.max && taken_ids.contains(&id_schema.make_id(next_number)) {
+                        next_number += 1;
+                    }
+                    if next_number >= id_schema.max {
+                        result.id_invalid.push(schueler);
+                        continue;
+                    }
+                    id_schema.make_id(next_number)
+                }
+            };
+            let gesch = schueler.gesch.to_string();
 
             match sqlx::query!(
-                "INSERT INTO schueler(id, gesch, age) VALUES (?,?,?)",
+                "INSERT INTO schueler(id, external_id, fName, lName, klasse, gesch, age) VALUES (?,?,?,?,?,?,?)",
                 id,
+                schueler.external_id,
+                schueler.fname,
+                schueler.lname,
+                schueler.klasse,
                 gesch,
                 age,
             )
             .execute(db)
             .await
             {
-                Ok(_) => result.valid.push(schueler),
+                Ok(_) => {
+                    taken_ids.insert(id);
+                    result.valid.push(schema::UploadSchueler { id: Some(id), ..schueler })
+                },
                 Err(_) => result.id_conflict.push(schueler),
             }
         }
-        return result;
+
+        let ids: Vec<i64> = result.valid.iter().filter_map(|s| s.id).collect();
+        let invalid = result.age_invalid.len() + result.gesch_invalid.len() + result.id_invalid.len() + result.id_conflict.len();
+        audit::record(auth_db, AuditAction {
+            actor: user.name(),
+            action: "upload_schueler",
+            event_id: user.event_id(),
+            after: Some(serde_json::json!({"ids": ids, "invalid": invalid}).to_string()),
+            ..Default::default()
+        }).await;
+        if !ids.is_empty() {
+            feed.publish(user.event_id(), FeedEvent::SchuelerUploaded { ids });
+        }
+        Ok(result)
+    }
+
+    /**
+     * Returns the id schema of the event. Events without one use the id range of the config
+     * without a check digit.
+     */
+    pub async fn get_id_schema(db: &SqlitePool, config: &Config) -> Result<schema::IdSchema, HttpResponse> {
+        match sqlx::query!("SELECT min_id, max_id, check_digit, use_external_ids FROM id_schema WHERE id = 1").fetch_optional(db).await {
+            Ok(Some(r)) => Ok(schema::IdSchema {
+                min: r.min_id,
+                max: r.max_id,
+                check_digit: r.check_digit,
+                use_external_ids: r.use_external_ids,
+            }),
+            Ok(None) => Ok(schema::IdSchema {
+                min: config.schueler_ids.start,
+                max: config.schueler_ids.end,
+                check_digit: false,
+                use_external_ids: false,
+            }),
+            Err(e) => Err(InternalServerf!("Error while getting the id schema of the event ({})", e))
+        }
+    }
+
+    /**
+     * sets the id schema of the event. This is only possible as long as there are no students.
+     *
+     * `actor` is written to the audit log of the auth db, for the event `event_id`.
+     */
+    pub async fn set_id_schema(id_schema: schema::IdSchema, actor: &str, event_id: &str, db: &SqlitePool, auth_db: &SqlitePool) -> Result<(), HttpResponse> {
+        if id_schema.min < 0 || id_schema.min >= id_schema.max {
+            return Err(BadRequestf!("The id range {}..{} is empty", id_schema.min, id_schema.max));
+        }
+        let schueler_count = match sqlx::query!("SELECT COUNT(*) as count FROM schueler").fetch_one(db).await {
+            Ok(r) => r.count,
+            Err(e) => return Err(InternalServerf!("Error while counting the students ({})", e))
+        };
+        if schueler_count > 0 {
+            return Err(Conflict!("The id schema can't be changed after students where uploaded"));
+        }
+
+        if let Err(e) = sqlx::query!("INSERT OR REPLACE INTO id_schema(id, min_id, max_id, check_digit, use_external_ids) VALUES (1,?,?,?,?)",
+            id_schema.min, id_schema.max, id_schema.check_digit, id_schema.use_external_ids)
+            .execute(db).await {
+                return Err(InternalServerf!("Error while saving the id schema ({})", e));
+        }
+        audit::record(auth_db, AuditAction {
+            actor,
+            action: "set_id_schema",
+            event_id: Some(event_id),
+            after: Some(serde_json::to_string(&id_schema).unwrap_or_default()),
+            ..Default::default()
+        }).await;
+        Ok(())
     }
     pub async fn get_all_versuch_for_kat(
         id: i32,
         kat_id: i32,
+        role: &Role,
         db: &SqlitePool,
     ) -> Result<Vec<model::NormVersuch>, HttpResponse> {
+        check_can_see(id as i64, role, db).await?;
         return match sqlx::query_as!(model::NormVersuch, "SELECT id, schuelerId as schueler_id, kategorieId as kategorie_id, wert, isReal as is_real, mTime as ts_recording FROM versuch WHERE schuelerId = ? AND kategorieId = ? AND isReal = true", id, kat_id).fetch_all(db).await {
             Ok(r) => Ok(r),
             Err(e) => Err(InternalServerf!("There was an error with the query {}",e))
@@ -232,10 +450,12 @@ pub mod interact {
     pub async fn get_top_versuch_by_kat(
         id: i32,
         kat_id: i32,
+        role: &Role,
         db: &SqlitePool,
         dosb_db: &SqlitePool,
         bjs_db: &SqlitePool,
     ) -> Result<model::NormVersuch, HttpResponse> {
+        check_can_see(id as i64, role, db).await?;
         // get all attempts of the student
         let attempts_rec = match sqlx::query!("SELECT kategorieId as category, wert as result FROM versuch WHERE schuelerId = ? AND kategorieId = ? and isReal = true", id, kat_id).fetch_all(db).await {
             Ok(r) => r,
@@ -286,9 +506,11 @@ pub mod interact {
 
     pub async fn get_top_versuch_in_bjs(
         id: i32,
+        role: &Role,
         db: &SqlitePool,
         bjs_db: &SqlitePool,
     ) -> Result<Vec<schema::NormVersuchBJS>, HttpResponse> {
+        check_can_see(id as i64, role, db).await?;
         let attempts = get_attempts(id.clone() as i64, db).await?;
         let (age, gender) = get_schueler_data(id.clone() as i64, db).await?;
 
@@ -329,9 +551,11 @@ pub mod interact {
 
     pub async fn get_top_versuch_in_dosb(
         id: i32,
+        role: &Role,
         db: &SqlitePool,
         dosb_db: &SqlitePool,
     ) -> Result<Vec<schema::NormVersuchDosb>, HttpResponse> {
+        check_can_see(id as i64, role, db).await?;
         let attempts = get_attempts(id.clone() as i64, db).await?;
         let (age, gender) = get_schueler_data(id.clone() as i64, db).await?;
 
@@ -369,7 +593,8 @@ pub mod interact {
         return Ok(top_dosb_result);
     }
 
-    pub async fn get_bjs_points(id: i32, db: &SqlitePool, bjs_db: &SqlitePool) -> Result<i32, HttpResponse> {
+    pub async fn get_bjs_points(id: i32, role: &Role, db: &SqlitePool, bjs_db: &SqlitePool) -> Result<i32, HttpResponse> {
+        check_can_see(id as i64, role, db).await?;
         let attempts = get_attempts(id.clone() as i64, db).await?;
         let (age, gender) = get_schueler_data(id.clone() as i64, db).await?;
 
@@ -383,10 +608,12 @@ pub mod interact {
     pub async fn needs_kat(
         schueler_id: i32,
         kategorie_id: i32,
+        role: &Role,
         db: &SqlitePool,
         dosb_db: &SqlitePool,
         bjs_db: &SqlitePool
     ) -> Result<schema::NeedsKat, HttpResponse> {
+        check_can_see(schueler_id as i64, role, db).await?;
         let (age, gender) = get_schueler_data(schueler_id.clone() as i64, db).await?;
 
         let bjs_evaluator= BJSEvaluator {
@@ -404,14 +631,29 @@ pub mod interact {
         });
     }
 
+    /**
+     * Adds a attempt. `vouch_name` is the id of the tmp user (or the admin) that recorded it. If
+     * the tmp user is bound to stations, only attempts for these kategorien are accepted.
+     *
+     * The ids are checked against the id schema when the students are uploaded, so here it is
+     * enough that the student exists.
+     */
     pub async fn add_versuch(
         versuch: schema::SimpleVersuch,
-        vouch_name: String,
+        user: &AuthUser,
         db: &SqlitePool,
+        auth_db: &SqlitePool,
+        feed: &EventFeed,
     ) -> Result<i32, HttpResponse> {
-        if !check_schueler_id(&versuch.schueler_id) {
-            return Err(NotFoundf!("The schueler with the id {} was not found", versuch.schueler_id));
+        let vouch_name = user.name();
+        // only tmp users are bound to stations, admins may record every kategorie
+        if let AuthUser::TmpUser { id, .. } = user {
+            let stations = auth::get_stations(auth_db, id).await?;
+            if !stations.is_empty() && !stations.contains(&(versuch.kategorie_id as i64)) {
+                return Err(Forbiddenf!("{} may not record attempts for the kategorie {}", id, versuch.kategorie_id));
+            }
         }
+
         if !check_kategorie_id(&versuch.kategorie_id, db).await {
             return Err(NotFoundf!("The category {} was not found", versuch.kategorie_id));
         }
@@ -422,24 +664,63 @@ pub mod interact {
             .unwrap()
             .as_secs() as i64;
 
-        let v = sqlx::query_as!(model::VersuchId, r#"
-    INSERT INTO versuch(id, aufsichtId, schuelerId, kategorieId, wert, mTime, isReal) VALUES ((SELECT ifNUll(MAX(id)+1, 1) FROM versuch), ?, ?, ?, ?, ?, true) RETURNING id;
-    "#, vouch_name, versuch.schueler_id, versuch.kategorie_id, versuch.wert, current_timestamp)
-            .fetch_one(db).await.unwrap();
-
-        return Ok(v.id.unwrap() as i32);
+        // the attempt is only added if the student exists, a typo in the id adds nothing
+        let v = match sqlx::query_as!(model::VersuchId, r#"
+    INSERT INTO versuch(id, aufsichtId, schuelerId, kategorieId, wert, mTime, isReal)
+    SELECT (SELECT ifNUll(MAX(id)+1, 1) FROM versuch), ?, ?, ?, ?, ?, true WHERE EXISTS (SELECT id FROM schueler WHERE id = ?) RETURNING id;
+    "#, vouch_name, versuch.schueler_id, versuch.kategorie_id, versuch.wert, current_timestamp, versuch.schueler_id)
+            .fetch_optional(db).await {
+                Ok(Some(v)) => v,
+                Ok(None) => return Err(NotFoundf!("The schueler with the id {} was not found (check the id for typos)", versuch.schueler_id)),
+                Err(e) => return Err(InternalServerf!("Error while adding the attempt ({})", e))
+        };
+        let id = v.id.unwrap_or_default() as i32;
+
+        audit::record(auth_db, AuditAction {
+            actor: vouch_name,
+            action: "add_versuch",
+            event_id: user.event_id(),
+            target: Some(format!("versuch:{}", id)),
+            after: Some(serde_json::to_string(&versuch).unwrap_or_default()),
+            ..Default::default()
+        }).await;
+        feed.publish(user.event_id(), FeedEvent::VersuchAdded {
+            versuch_id: id,
+            schueler_id: versuch.schueler_id,
+            kategorie_id: versuch.kategorie_id,
+            wert: versuch.wert,
+        });
+        Ok(id)
     }
 
-    pub async fn set_is_real(id: i32, is_real: bool, db: &SqlitePool) -> bool {
+    /// marks a attempt as real or not. The change is written to the audit log of the auth db and published to the feed.
+    pub async fn set_is_real(id: i32, is_real: bool, user: &AuthUser, db: &SqlitePool, auth_db: &SqlitePool, feed: &EventFeed) -> bool {
+        let before = match sqlx::query!("SELECT isReal as is_real FROM versuch WHERE id = ?", id).fetch_optional(db).await {
+            Ok(Some(v)) => v.is_real,
+            _ => return false
+        };
         let r = sqlx::query("UPDATE versuch SET isReal = ? WHERE id = ?")
             .bind(is_real)
             .bind(id)
             .execute(db)
             .await;
-        return match r {
+        let changed = match r {
             Err(_e) => false,
             Ok(q) => q.rows_affected() == 1,
         };
+
+        if changed {
+            audit::record(auth_db, AuditAction {
+                actor: user.name(),
+                action: "set_is_real",
+                event_id: user.event_id(),
+                target: Some(format!("versuch:{}", id)),
+                before: Some(before.to_string()),
+                after: Some(is_real.to_string()),
+            }).await;
+            feed.publish(user.event_id(), FeedEvent::VersuchChanged { versuch_id: id, is_real });
+        }
+        changed
     }
 
     pub async fn get_all_kat(db: &SqlitePool) -> Vec<schema::SimpleKategorie> {
@@ -462,6 +743,15 @@ pub mod interact {
             .collect();
     }
 
+    /// the kategorien of the station of a tmp user. Without a station these are all kategorien.
+    pub async fn get_station_kat(tmp_user_id: String, db: &SqlitePool, auth_db: &SqlitePool) -> Result<Vec<schema::SimpleKategorie>, HttpResponse> {
+        let stations = auth::get_stations(auth_db, &tmp_user_id).await?;
+        Ok(get_all_kat(db).await
+            .into_iter()
+            .filter(|k| stations.is_empty() || stations.contains(&(k.id as i64)))
+            .collect())
+    }
+
     pub async fn get_kategorie(id: i32, db: &SqlitePool) -> schema::Kategorie {
         let result = sqlx::query_as!(model::Kategorie, r#"
         SELECT id, name, einheit, maxVers as max_vers, digits_before, digits_after FROM kategorien WHERE id = ?
@@ -503,12 +793,94 @@ pub mod interact {
             kat_group_id: 0,
         }
     }
-
-    fn check_schueler_id(id: &i32) -> bool {
-        return id < &9999 && id >= &1000;
-    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::schema::IdSchema;
+
+    #[test]
+    fn id_schema_check_digit() {
+        let id_schema = IdSchema { min: 1000, max: 9999, check_digit: true, use_external_ids: false };
+        // 1234 has the luhn check digit 4
+        assert_eq!(id_schema.make_id(1234), 12344);
+        assert!(id_schema.is_valid(12344));
+        // a wrong digit and swapped digits are caught
+        assert!(!id_schema.is_valid(12345));
+        assert!(!id_schema.is_valid(12434));
+        // out of range
+        assert!(!id_schema.is_valid(id_schema.make_id(999)));
+
+        let plain = IdSchema { check_digit: false, ..id_schema };
+        assert!(plain.is_valid(1234));
+        assert!(!plain.is_valid(9999));
+    }
+
+    #[sqlx::test(migrator = "crate::auth::MIGRATOR")]
+    async fn upload_with_external_ids(auth_db: sqlx::SqlitePool) {
+        use crate::auth::AuthUser;
+        use crate::config::Config;
+        use crate::feed::EventFeed;
+        use crate::schema::{SimpleVersuch, UploadSchueler};
+
+        let db = crate::manage::test_event_db(&[], &[]).await;
+        crate::interact::set_id_schema(IdSchema { min: 1000, max: 2000, check_digit: true, use_external_ids: true }, "admin", "ev", &db, &auth_db).await.unwrap();
+
+        let schueler = |external_id| UploadSchueler {
+            id: None, external_id, fname: None, lname: None, klasse: Some("5a".to_string()), gesch: 'w', age: Some(11), bday: None,
+        };
+        let user = AuthUser::Admin { api_key: String::new(), username: "admin".to_string() };
+        let config = Config::default();
+        let feed = EventFeed::default();
+        let result = crate::interact::upload_schueler(vec![schueler(Some(987654)), schueler(None)], &user, &db, &auth_db, &config, &feed).await.unwrap();
+        // the external id is neither in the range nor has a check digit
+        let ids: Vec<Option<i64>> = result.valid.iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![Some(987654), Some(10009)]);
+
+        let versuch = |schueler_id| SimpleVersuch { schueler_id, wert: 3.2, kategorie_id: 4 };
+        crate::interact::add_versuch(versuch(987654), &user, &db, &auth_db, &feed).await.unwrap();
+        assert!(crate::interact::add_versuch(versuch(10008), &user, &db, &auth_db, &feed).await.is_err());
+
+        // a tmp user with the same name as the admin is bound to other stations, that doesn't matter for the admin
+        sqlx::query("INSERT INTO tmp_user(id, api_key, vouched, time_of_creation, last_refresh) VALUES ('admin', 'x', true, 0, 0)").execute(&auth_db).await.unwrap();
+        sqlx::query("INSERT INTO tmp_user_station(tmp_user_id, kategorie_id) VALUES ('admin', 1)").execute(&auth_db).await.unwrap();
+        crate::interact::add_versuch(versuch(987654), &user, &db, &auth_db, &feed).await.unwrap();
+        let helper = AuthUser::TmpUser { id: "admin".to_string(), api_key: String::new(), event_id: "ev".to_string() };
+        assert!(crate::interact::add_versuch(versuch(987654), &helper, &db, &auth_db, &feed).await.is_err());
+    }
+
+    #[sqlx::test]
+    async fn card_of_schueler() {
+        use crate::auth::roles::Role;
+        use crate::bjs_eval::BJSAbzeichen;
+        use sqlx::SqlitePool;
+
+        let db = crate::manage::test_event_db(
+            &[(1234, "Muster", "5a", 'm', 11)],
+            &[(1234, 1, 8.1, true), (1234, 1, 7.7, true), (1234, 4, 3.3, true), (1234, 6, 40.0, false)],
+        ).await;
+        let dosb_db = SqlitePool::connect("testData/2025dosb.db").await.unwrap();
+        let bjs_db = SqlitePool::connect("testData/2025bjs.db").await.unwrap();
+
+        let role = Role::test(None);
+        let karte = crate::interact::get_schueler_karte(1234, &role, &db, &dosb_db, &bjs_db).await.unwrap();
+        assert_eq!(karte.last_name, "Muster");
+        // the 50m and the weitsprung are done, the schlagball attempt is not real
+        assert_eq!(karte.bjs_urkunde, BJSAbzeichen::Teilnehmer);
+        let fertig: Vec<bool> = karte.bjs_gruppen.iter().map(|g| g.fertig).collect();
+        assert_eq!(fertig, vec![true, true, false, false]);
+
+        let lauf = &karte.bjs_gruppen[0].kategorien[0];
+        assert_eq!((lauf.id, lauf.bester_wert, lauf.versuche, lauf.versuche_uebrig), (1, Some(7.7), 2, Some(1)));
+        let weitsprung = &karte.bjs_gruppen[1].kategorien[0];
+        assert_eq!(weitsprung.versuche_uebrig, None);
+        let schlagball = &karte.bjs_gruppen[2].kategorien[0];
+        assert_eq!((schlagball.id, schlagball.bester_wert, schlagball.versuche), (6, None, 0));
+        assert!(karte.dosb_gruppen.iter().any(|g| !g.fertig));
+
+        assert!(crate::interact::get_schueler_karte(4321, &role, &db, &dosb_db, &bjs_db).await.is_err());
+        // class teachers only see their class
+        assert!(crate::interact::get_schueler_karte(1234, &Role::test(Some("5b")), &db, &dosb_db, &bjs_db).await.is_err());
+        crate::interact::get_schueler_karte(1234, &Role::test(Some("5a")), &db, &dosb_db, &bjs_db).await.unwrap();
+    }
 }
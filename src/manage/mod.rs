@@ -1,24 +1,226 @@
 pub mod schema;
+pub mod registry;
 
 use log::{debug, info, warn};
-use sqlx::migrate::MigrateDatabase;
+use sqlx::migrate::{MigrateDatabase, Migrator};
 use sqlx::{Sqlite, SqlitePool};
 use std::fs;
 use std::fs::File;
-use std::path::Path;
 use std::string::String;
 use std::ffi::OsStr;
 use walkdir;
 use actix_web::HttpResponse;
-use crate::{InternalServer,Conflict,NotFound};
+use crate::{InternalServer,Conflict,NotFound,NotFoundf,InternalServerf,Conflictf};
+use crate::config::Config;
+use crate::audit::{self, AuditAction};
 
+/// The schema of every event database. Event databases are upgraded with this on open.
+static EVENT_MIGRATOR: Migrator = sqlx::migrate!("./event_migrations");
+
+/**
+ * Creates the database of a new event and registers it in the auth db under the name of `data`.
+ * If anything goes wrong the half created database is removed again. `actor` is written to the
+ * audit log of the auth db.
+ */
 pub async fn create_event(
-    school_dir: String,
-    vorlagen_dir: String,
+    config: &Config,
     id: String,
     data: schema::EventConstructor,
+    auth_db: &SqlitePool,
+    actor: &str,
 ) -> Result<SqlitePool, HttpResponse> {
-    let db_url = format!("{}{}.db", school_dir, id);
+    let con = create_event_db(config, id.clone()).await?;
+
+    let setup = async {
+        if let Some(kategorien) = data.kategorien {
+            for kat in kategorien {
+                let _ = match kat {
+                    // TODO This will not work!
+                    schema::ConstructKategorie::Kategorie(_) => panic!("Kategorien werden nicht mehr supported"),//insert_kat_in_db(&con, k).await,
+                    // only ever use vorlagen
+                    schema::ConstructKategorie::Vorlage(v) => {
+                        insert_kat_in_db(
+                            &con,
+                            get_kat_from_vorlage(config, 2025, v.id)?
+                        )
+                        .await
+                    }
+                };
+            }
+        }
+
+        if let Some(id_schema) = data.id_schema {
+            crate::interact::set_id_schema(id_schema, actor, &id, &con, auth_db).await?;
+        }
+
+        register_event(auth_db, &id, &data.name).await
+    }.await;
+    if let Err(e) = setup {
+        remove_event_db(config, &id, con).await;
+        return Err(e);
+    }
+
+    audit::record(auth_db, AuditAction { actor, action: "create_event", event_id: Some(&id), ..Default::default() }).await;
+    Ok(con)
+}
+
+/**
+ * Creates a new event with the same configuration as an existing one.
+ *
+ * The kategorien and the id schema of the source event are copied. The kategorien keep their
+ * ids, so attempts in the new event still match the categories of the BJS and DOSB databases.
+ * If `copy_schueler` is set the students are copied as well, one year older. The new event is
+ * registered in the auth db under `new_name` and `actor` is written to its audit log. Like with
+ * `create_event` the new database is removed again if the copy fails.
+ */
+pub async fn clone_event(
+    config: &Config,
+    source_id: String,
+    new_id: String,
+    new_name: String,
+    copy_schueler: bool,
+    auth_db: &SqlitePool,
+    actor: &str,
+) -> Result<SqlitePool, HttpResponse> {
+    let source = open_event(config, source_id.clone()).await?;
+
+    let kategorien = match sqlx::query!("SELECT id, name, einheit, maxVers as max_vers, digits_before, digits_after FROM kategorien")
+        .fetch_all(&source).await {
+            Ok(r) => r,
+            Err(e) => return Err(InternalServerf!("Error while reading the kategorien of {} ({})", source_id, e))
+    };
+
+    let id_schema = match sqlx::query!("SELECT min_id, max_id, check_digit, use_external_ids FROM id_schema")
+        .fetch_optional(&source).await {
+            Ok(r) => r,
+            Err(e) => return Err(InternalServerf!("Error while reading the id schema of {} ({})", source_id, e))
+    };
+
+    let schueler = if copy_schueler {
+        match sqlx::query!("SELECT id, external_id, fName as fname, lName as lname, klasse, gesch, birth_year, age, aufsicht FROM schueler")
+            .fetch_all(&source).await {
+                Ok(r) => r,
+                Err(e) => return Err(InternalServerf!("Error while reading the schueler of {} ({})", source_id, e))
+        }
+    } else {
+        vec![]
+    };
+    source.close().await;
+
+    let con = create_event_db(config, new_id.clone()).await?;
+
+    let setup = async {
+        for kat in kategorien {
+            if let Err(e) = sqlx::query!("INSERT INTO kategorien(id, name, einheit, maxVers, digits_before, digits_after) VALUES (?,?,?,?,?,?)",
+                kat.id, kat.name, kat.einheit, kat.max_vers, kat.digits_before, kat.digits_after)
+                .execute(&con).await {
+                    return Err(InternalServerf!("Error while copying the kategorie {} ({})", kat.id, e));
+            }
+        }
+
+        if let Some(i) = id_schema {
+            if let Err(e) = sqlx::query!("INSERT INTO id_schema(id, min_id, max_id, check_digit, use_external_ids) VALUES (1,?,?,?,?)",
+                i.min_id, i.max_id, i.check_digit, i.use_external_ids)
+                .execute(&con).await {
+                    return Err(InternalServerf!("Error while copying the id schema ({})", e));
+            }
+        }
+
+        for s in schueler {
+            // the students are a year older by the next event
+            let age = s.age.map(|a| a + 1);
+            if let Err(e) = sqlx::query!("INSERT INTO schueler(id, external_id, fName, lName, klasse, gesch, birth_year, age, aufsicht) VALUES (?,?,?,?,?,?,?,?,?)",
+                s.id, s.external_id, s.fname, s.lname, s.klasse, s.gesch, s.birth_year, age, s.aufsicht)
+                .execute(&con).await {
+                    return Err(InternalServerf!("Error while copying the schueler {:?} ({})", s.id, e));
+            }
+        }
+
+        register_event(auth_db, &new_id, &new_name).await
+    }.await;
+    if let Err(e) = setup {
+        remove_event_db(config, &new_id, con).await;
+        return Err(e);
+    }
+
+    info!("cloned event {} into {}", source_id, new_id);
+    audit::record(auth_db, AuditAction {
+        actor,
+        action: "clone_event",
+        event_id: Some(&new_id),
+        before: Some(source_id),
+        after: Some(format!("copy_schueler {}", copy_schueler)),
+        ..Default::default()
+    }).await;
+
+    Ok(con)
+}
+
+/// the newest event schema version this library knows about
+pub fn latest_event_version() -> i64 {
+    EVENT_MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0)
+}
+
+/**
+ * Returns the schema version of an event database.
+ *
+ * That is the newest migration that was applied to it. A database without any migrations has
+ * the version 0.
+ */
+pub async fn get_event_version(db: &SqlitePool) -> Result<i64, HttpResponse> {
+    let has_migrations: Option<(String,)> = match sqlx::query_as("SELECT name FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations'")
+        .fetch_optional(db).await {
+            Ok(r) => r,
+            Err(e) => return Err(InternalServerf!("Error while reading the schema of the event ({})", e))
+    };
+    if has_migrations.is_none() {
+        return Ok(0);
+    }
+
+    match sqlx::query_as::<_, (Option<i64>,)>("SELECT MAX(version) FROM _sqlx_migrations WHERE success = true")
+        .fetch_one(db).await {
+            Ok(r) => Ok(r.0.unwrap_or(0)),
+            Err(e) => Err(InternalServerf!("Error while reading the schema version of the event ({})", e))
+    }
+}
+
+/**
+ * Opens an existing event and brings its schema up to date.
+ *
+ * All pending event migrations are applied. Events that were written by a newer version of
+ * this library are refused, because the queries of this version would not match them.
+ */
+pub async fn open_event(config: &Config, id: String) -> Result<SqlitePool, HttpResponse> {
+    let db_url = config.event_db(&id)?;
+    if !Sqlite::database_exists(db_url.as_str()).await.unwrap_or(false) {
+        return Err(NotFoundf!("The event {} was not found", id));
+    }
+
+    let con = match SqlitePool::connect(db_url.as_str()).await {
+        Ok(c) => c,
+        Err(e) => return Err(InternalServerf!("Error while opening the event {} ({})", id, e))
+    };
+
+    let version = get_event_version(&con).await?;
+    if version > latest_event_version() {
+        con.close().await;
+        return Err(Conflictf!("The event {} has the schema version {}, but this version only supports up to {}", id, version, latest_event_version()));
+    }
+
+    if let Err(e) = EVENT_MIGRATOR.run(&con).await {
+        con.close().await;
+        return Err(InternalServerf!("Error while upgrading the event {} from version {} ({})", id, version, e));
+    }
+    if version < latest_event_version() {
+        info!("upgraded event {} from version {} to {}", id, version, latest_event_version());
+    }
+
+    Ok(con)
+}
+
+/// creates the database file of a new event and writes the event schema into it
+async fn create_event_db(config: &Config, id: String) -> Result<SqlitePool, HttpResponse> {
+    let db_url = config.event_db(&id)?;
     // check if database exists
     if Sqlite::database_exists(db_url.as_str())
         .await
@@ -38,34 +240,47 @@ pub async fn create_event(
     };
     info!("created DB");
 
-    let con = SqlitePool::connect(db_url.as_str()).await.unwrap();
+    let con = match SqlitePool::connect(db_url.as_str()).await {
+        Ok(c) => c,
+        Err(e) => {
+            let _ = fs::remove_file(&db_url);
+            return Err(InternalServerf!("Error while opening the new event {} ({})", id, e));
+        }
+    };
 
     // write the database schema to the file
-    sqlx::migrate!("./event_migrations").run(&con).await.unwrap();
+    if let Err(e) = EVENT_MIGRATOR.run(&con).await {
+        remove_event_db(config, &id, con).await;
+        return Err(InternalServerf!("Error while writing the schema of the event {} ({})", id, e));
+    }
 
     info!("migrated DB");
-    if data.kategorien.is_some() {
-        for kat in data.kategorien.unwrap() {
-            let _ = match kat {
-                // TODO This will not work!
-                schema::ConstructKategorie::Kategorie(_) => panic!("Kategorien werden nicht mehr supported"),//insert_kat_in_db(&con, k).await,
-                // only ever use vorlagen
-                schema::ConstructKategorie::Vorlage(v) => {
-                    insert_kat_in_db(
-                        &con,
-                        get_kat_from_vorlage(vorlagen_dir.clone(), 2025, v.id)?
-                    )
-                    .await
-                }
-            };
+
+    return Ok(con);
+}
+
+/// closes and deletes the database of an event that could not be set up completely
+async fn remove_event_db(config: &Config, id: &str, con: SqlitePool) {
+    con.close().await;
+    // the id was checked when the database was created
+    if let Ok(path) = config.event_db(id) {
+        if let Err(e) = fs::remove_file(path) {
+            warn!("Couldnt remove the unfinished event {}: {}", id, e);
         }
     }
+}
 
-    return Ok(con);
+/// adds an event to the auth db, so users can be bound to it
+async fn register_event(auth_db: &SqlitePool, id: &str, name: &str) -> Result<(), HttpResponse> {
+    match sqlx::query!("INSERT INTO event(id, name) VALUES (?,?)", id, name).execute(auth_db).await {
+        Ok(_) => Ok(()),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Err(Conflictf!("The event {} is already registered in the auth db", id)),
+        Err(e) => Err(InternalServerf!("Error while registering the event {} in the auth db ({})", id, e))
+    }
 }
 
-pub fn get_vorlagen(vorlagen_path: String) -> Vec<String> {
-    let vorlagen_path = Path::new(&vorlagen_path);
+pub fn get_vorlagen(config: &Config) -> Vec<String> {
+    let vorlagen_path = config.vorlagen_dir.as_path();
     let mut vorlagen = vec![];
     if vorlagen_path.is_dir() {
         for entry in fs::read_dir(vorlagen_path).unwrap() {
@@ -82,11 +297,11 @@ pub fn get_vorlagen(vorlagen_path: String) -> Vec<String> {
 }
 
 pub fn get_kat_list_from_vorlage(
-    vorlagen_path: String,
+    config: &Config,
     year: i32,
 ) -> Result<Vec<schema::OutsideKategorie>, HttpResponse> {
     let mut kat_list: Vec<schema::OutsideKategorie> = vec![];
-    for entry_result in walkdir::WalkDir::new([vorlagen_path, year.to_string()].join("")) {
+    for entry_result in walkdir::WalkDir::new(config.vorlagen_year_dir(year as i64)) {
         if let Ok(entry) = entry_result {
             debug!("Path: {:?}", entry.path());
             if entry.file_name() != "init.json" {
@@ -122,8 +337,8 @@ pub fn get_kat_list_from_vorlage(
 /**
  * checks the given Vorlagen Path for Syntax error and if the all the Attributes are there
  */
-pub fn check_vorlagen(vorlagen_dir: String) -> Result<(), String> {
-    let vorlagen = walkdir::WalkDir::new(vorlagen_dir);
+pub fn check_vorlagen(config: &Config) -> Result<(), String> {
+    let vorlagen = walkdir::WalkDir::new(&config.vorlagen_dir);
     // walks through the json files in the vorlage
     for vorlage in vorlagen {
         // if it checksout
@@ -179,14 +394,12 @@ pub fn check_vorlagen(vorlagen_dir: String) -> Result<(), String> {
 }
 
 pub fn get_kat_from_vorlage(
-    vorlagen_dir: String,
+    config: &Config,
     year: i32,
     kat_id: i32,
 ) -> Result<schema::Kategorie, HttpResponse> {
-    let path_string = format!("{}{}/{}.json", vorlagen_dir, year, kat_id);
-
-    let path = Path::new(&path_string);
-    let reader = match File::open(path) {
+    let path = config.vorlagen_year_dir(year as i64).join(format!("{}.json", kat_id));
+    let reader = match File::open(&path) {
         Err(_) => {
             return Err(NotFound!(format!( "Kategorie {:?} konnte nicht gefunden werden", path)));
         }
@@ -202,10 +415,10 @@ pub fn get_kat_from_vorlage(
 }
 
 pub fn get_kat_by_vorlage(
-    vorlagen_path: String,
+    config: &Config,
     vorlage: i64,
 ) -> Result<Vec<schema::Kategorie>, HttpResponse> {
-    let files = match fs::read_dir([vorlagen_path, vorlage.to_string(), "/".to_string()].join("")) {
+    let files = match fs::read_dir(config.vorlagen_year_dir(vorlage)) {
         Ok(p) => p,
         Err(_) => {
             return Err(InternalServer!("Vorlagen Dir not found"))
@@ -256,18 +469,104 @@ async fn insert_kat_in_db(db: &SqlitePool, kat: schema::Kategorie) -> Result<(),
     Ok(())
 }
 
+/// a config with its own empty data dir for tests, the dir is removed when the `TempDir` is dropped
+#[cfg(test)]
+pub(crate) fn test_config(config: Config) -> (tempfile::TempDir, Config) {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config { data_dir: dir.path().to_path_buf(), ..config };
+    (dir, config)
+}
+
+/**
+ * A event database in memory with the real schema for tests.
+ *
+ * It has the kategorien 50m Lauf (1), Weitsprung (4), Schlagball (6) and 800m Lauf (14), the
+ * students `(id, name, klasse, gesch, age)` and the attempts `(schueler_id, kategorie_id, wert,
+ * is_real)`. The name is used as first and last name, the attempts are recorded at time 0.
+ */
+#[cfg(test)]
+pub(crate) async fn test_event_db(schueler: &[(i64, &str, &str, char, i64)], versuche: &[(i64, i64, f64, bool)]) -> SqlitePool {
+    let db = sqlx::sqlite::SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+    EVENT_MIGRATOR.run(&db).await.unwrap();
+    sqlx::raw_sql(r#"
+        INSERT INTO kategorien(id, name, einheit, maxVers, digits_before, digits_after) VALUES
+            (1, '50m Lauf', 's', 3, 1, 1), (4, 'Weitsprung', 'm', NULL, 1, 2), (6, 'Schlagball', 'm', 3, 2, 1), (14, '800m Lauf', 's', 1, 3, 0);
+    "#).execute(&db).await.unwrap();
+    for (id, name, klasse, gesch, age) in schueler {
+        sqlx::query("INSERT INTO schueler(id, fName, lName, klasse, gesch, age) VALUES (?,?,?,?,?,?)")
+            .bind(id).bind(name).bind(name).bind(klasse).bind(gesch.to_string()).bind(age)
+            .execute(&db).await.unwrap();
+    }
+    for (i, (schueler_id, kategorie_id, wert, is_real)) in versuche.iter().enumerate() {
+        sqlx::query("INSERT INTO versuch(id, aufsichtId, schuelerId, kategorieId, wert, mTime, isReal) VALUES (?,'x',?,?,?,0,?)")
+            .bind(i as i64 + 1).bind(schueler_id).bind(kategorie_id).bind(wert).bind(is_real)
+            .execute(&db).await.unwrap();
+    }
+    db
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     #[test]
     pub fn test_vorlagen() {
-        check_vorlagen("vorlagen/".to_string()).unwrap();
+        check_vorlagen(&Config::default()).unwrap();
     }
 
     #[test]
     pub fn get_list_of_events() {
-        let k_r = get_kat_list_from_vorlage("vorlagen/".to_string(), 2023);
+        let k_r = get_kat_list_from_vorlage(&Config::default(), 2023);
         let k = k_r.unwrap();
         println!("{:?}", k);
     }
+
+    #[sqlx::test(migrator = "crate::auth::MIGRATOR")]
+    async fn clone_event_with_schueler(auth_db: SqlitePool) {
+        let (_dir, config) = test_config(Config::default());
+
+        let source = create_event_db(&config, "src".to_string()).await.unwrap();
+        sqlx::query("INSERT INTO kategorien(id, name, einheit, maxVers, digits_before, digits_after) VALUES (4, 'Weitsprung', 'm', 3, 1, 2)")
+            .execute(&source).await.unwrap();
+        sqlx::query("INSERT INTO schueler(id, gesch, age) VALUES (1234, 'w', 12)")
+            .execute(&source).await.unwrap();
+        source.close().await;
+
+        let con = clone_event(&config, "src".to_string(), "dst".to_string(), "Sportfest".to_string(), true, &auth_db, "admin").await.unwrap();
+        let kat: (i64, String) = sqlx::query_as("SELECT id, name FROM kategorien").fetch_one(&con).await.unwrap();
+        assert_eq!(kat, (4, "Weitsprung".to_string()));
+        let age: (i64,) = sqlx::query_as("SELECT age FROM schueler WHERE id = 1234").fetch_one(&con).await.unwrap();
+        assert_eq!(age.0, 13);
+        let name: (String,) = sqlx::query_as("SELECT name FROM event WHERE id = 'dst'").fetch_one(&auth_db).await.unwrap();
+        assert_eq!(name.0, "Sportfest");
+        let log = audit::get_audit_log(&auth_db, Some("dst".to_string()), None).await.unwrap();
+        assert_eq!((log[0].actor.as_str(), log[0].action.as_str()), ("admin", "clone_event"));
+
+        // the target must not exist yet
+        assert!(clone_event(&config, "src".to_string(), "dst".to_string(), "Sportfest".to_string(), false, &auth_db, "admin").await.is_err());
+        // a failed clone leaves no event file behind
+        sqlx::query("INSERT INTO event(id, name) VALUES ('taken', 'Sportfest 2024')").execute(&auth_db).await.unwrap();
+        assert!(clone_event(&config, "src".to_string(), "taken".to_string(), "Sportfest".to_string(), false, &auth_db, "admin").await.is_err());
+        assert!(!std::path::Path::new(&config.event_db("taken").unwrap()).exists());
+        assert!(clone_event(&config, "src".to_string(), "../dst".to_string(), "Sportfest".to_string(), false, &auth_db, "admin").await.is_err());
+    }
+
+    #[sqlx::test]
+    async fn open_event_versions() {
+        let (_dir, config) = test_config(Config::default());
+
+        assert!(open_event(&config, "missing".to_string()).await.is_err());
+
+        let con = create_event_db(&config, "ev".to_string()).await.unwrap();
+        assert_eq!(get_event_version(&con).await.unwrap(), latest_event_version());
+        con.close().await;
+
+        let con = open_event(&config, "ev".to_string()).await.unwrap();
+        // pretend a newer library already touched this event
+        sqlx::query("INSERT INTO _sqlx_migrations(version, description, success, checksum, execution_time) VALUES (?, 'future', true, X'00', 0)")
+            .bind(latest_event_version() + 1)
+            .execute(&con).await.unwrap();
+        con.close().await;
+
+        assert!(open_event(&config, "ev".to_string()).await.is_err());
+    }
 }
@@ -0,0 +1,145 @@
+//! Keeps the connections to the event databases of a server
+//!
+//! Events are opened the first time they are needed and closed again once nobody used them for
+//! a while. The `ensure_event` macro expects an `EventRegistry` in `data.event_cons`.
+//!
+//! Only one request opens an event at a time, the others wait for it, so the migrations of an
+//! event never run twice at once.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use sqlx::SqlitePool;
+use actix_web::HttpResponse;
+use log::info;
+use crate::config::Config;
+use super::open_event;
+
+struct OpenEvent {
+    pool: SqlitePool,
+    last_used: Instant,
+}
+
+pub struct EventRegistry {
+    config: Config,
+    idle_timeout: Duration,
+    events: Mutex<HashMap<String, OpenEvent>>,
+    /// a lock per event that is being opened right now
+    opening: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl EventRegistry {
+    /// the events are opened from the `data_dir` of the config
+    pub fn new(config: &Config) -> Self {
+        EventRegistry {
+            config: config.clone(),
+            idle_timeout: Duration::from_secs(config.event_idle_timeout),
+            events: Mutex::new(HashMap::new()),
+            opening: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /**
+     * Returns the pool of an event. If the event is not open yet it is opened (and upgraded) from
+     * the data dir.
+     */
+    pub async fn get(&self, id: &str) -> Result<SqlitePool, HttpResponse> {
+        if let Some(pool) = self.get_open(id) {
+            return Ok(pool);
+        }
+
+        let lock = self.opening.lock().unwrap().entry(id.to_string()).or_default().clone();
+        let guard = lock.lock().await;
+        // someone else may have opened it while we waited
+        let result = match self.get_open(id) {
+            Some(pool) => Ok(pool),
+            None => {
+                let result = open_event(&self.config, id.to_string()).await;
+                if let Ok(pool) = &result {
+                    self.insert(id.to_string(), pool.clone());
+                }
+                result
+            }
+        };
+        drop(guard);
+        // the last one that waited removes the lock, so a failed open is tried by one at a time as well
+        let mut opening = self.opening.lock().unwrap();
+        drop(lock);
+        if opening.get(id).map(|l| Arc::strong_count(l) == 1).unwrap_or(false) {
+            opening.remove(id);
+        }
+        result
+    }
+
+    fn get_open(&self, id: &str) -> Option<SqlitePool> {
+        let mut events = self.events.lock().unwrap();
+        let e = events.get_mut(id)?;
+        e.last_used = Instant::now();
+        Some(e.pool.clone())
+    }
+
+    /// adds an event that was just created, e.g. by `create_event` or `clone_event`
+    pub fn insert(&self, id: String, pool: SqlitePool) {
+        self.events.lock().unwrap().insert(id, OpenEvent { pool, last_used: Instant::now() });
+    }
+
+    /// the ids of all events that are open right now
+    pub fn open_events(&self) -> Vec<String> {
+        self.events.lock().unwrap().keys().cloned().collect()
+    }
+
+    /**
+     * Closes an event, for example before its file is deleted or replaced.
+     *
+     * The pool is only removed from the registry. Requests that still hold a clone of it can
+     * finish, the pool is closed when the last of them drops it.
+     */
+    pub fn close(&self, id: &str) {
+        self.events.lock().unwrap().remove(id);
+    }
+
+    /**
+     * Closes all events that where not used for longer than the idle timeout, like `close`.
+     *
+     * This should be called periodically by the server. Returns the number of closed events.
+     */
+    pub fn close_idle(&self) -> usize {
+        let mut events = self.events.lock().unwrap();
+        let before = events.len();
+        events.retain(|id, e| {
+            let idle = e.last_used.elapsed() >= self.idle_timeout;
+            if idle {
+                info!("closing idle event {}", id);
+            }
+            !idle
+        });
+        before - events.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manage::{create_event_db, test_config};
+
+    #[sqlx::test]
+    async fn open_and_close_idle() {
+        let (_dir, config) = test_config(Config { event_idle_timeout: 0, ..Config::default() });
+        create_event_db(&config, "ev".to_string()).await.unwrap().close().await;
+
+        let registry = EventRegistry::new(&config);
+        assert!(registry.get("../ev").await.is_err());
+        assert!(registry.get("unknown").await.is_err());
+
+        // the event is opened once, the second request waits for the first
+        let (pool, other) = futures::join!(registry.get("ev"), registry.get("ev"));
+        let pool = pool.unwrap();
+        other.unwrap();
+        assert_eq!(registry.open_events(), vec!["ev".to_string()]);
+        assert!(registry.opening.lock().unwrap().is_empty());
+
+        assert_eq!(registry.close_idle(), 1);
+        assert!(registry.open_events().is_empty());
+        // a request that still has the pool can go on using it
+        sqlx::query("SELECT id FROM schueler").fetch_all(&pool).await.unwrap();
+    }
+}
@@ -95,4 +95,5 @@ pub struct EventConstructor {
     pub vorlage: i32, // Here the Year from wich the vorlage should be used is spezified, if none is given the newest is used
     pub bjs_bewertung: Option<Vec<BjsAlterBewertung>>,
     pub kategorien: Option<Vec<ConstructKategorie>>,
+    pub id_schema: Option<crate::schema::IdSchema>,
 }
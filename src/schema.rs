@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use crate::dosb_eval::*;
+use crate::bjs_eval::BJSAbzeichen;
 
 #[derive(Debug, PartialEq, Serialize)]
 pub struct SimpleSchueler {
@@ -17,12 +18,71 @@ pub struct SimpleVersuch {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UploadSchueler {
-    pub id: i64,
+    /// if no id is given one is assigned by the importer
+    pub id: Option<i64>,
+    pub external_id: Option<i64>,
+    pub fname: Option<String>,
+    pub lname: Option<String>,
+    pub klasse: Option<String>,
     pub gesch: char,
     pub age: Option<i8>,
     pub bday: Option<String>,
 }
 
+/**
+ * How the ids of the students of an event look like.
+ *
+ * The ids are numbers in `min..max`. If `check_digit` is set, a check digit is appended to
+ * these numbers, so the real ids are ten times as big. That way most typos at the stations are
+ * caught. With `use_external_ids` the ids of the school administration are used as they are.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct IdSchema {
+    pub min: i64,
+    pub max: i64,
+    pub check_digit: bool,
+    pub use_external_ids: bool,
+}
+
+impl IdSchema {
+    /// turns a number of the range into a id
+    pub fn make_id(&self, number: i64) -> i64 {
+        if self.check_digit {
+            number * 10 + luhn_digit(number)
+        } else {
+            number
+        }
+    }
+
+    pub fn is_valid(&self, id: i64) -> bool {
+        if !self.check_digit {
+            return (self.min..self.max).contains(&id);
+        }
+        let number = id.div_euclid(10);
+        (self.min..self.max).contains(&number) && self.make_id(number) == id
+    }
+}
+
+/// the check digit of the luhn algorithm
+fn luhn_digit(number: i64) -> i64 {
+    let mut sum = 0;
+    let mut rest = number;
+    let mut double = true;
+    while rest > 0 {
+        let mut digit = rest % 10;
+        if double {
+            digit *= 2;
+            if digit > 9 {
+                digit -= 9;
+            }
+        }
+        sum += digit;
+        double = !double;
+        rest /= 10;
+    }
+    (10 - sum % 10) % 10
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct NormVersuchBJS {
     pub id: i64,
@@ -75,3 +135,40 @@ pub struct NeedsKat {
     pub dosb: bool,
     pub bjs: bool,
 }
+
+/// a needed kategorie on the card of a student
+#[derive(Debug, PartialEq, Serialize)]
+pub struct KartenKategorie {
+    pub id: i64,
+    pub name: String,
+    pub einheit: String,
+    pub bester_wert: Option<f64>,
+    pub versuche: i64,
+    /// `None` if the kategorie has no `maxVers`
+    pub versuche_uebrig: Option<i64>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct KartenGruppe {
+    pub id: i64,
+    pub fertig: bool,
+    /// the needed kategorien of the group that are part of the event
+    pub kategorien: Vec<KartenKategorie>,
+}
+
+/// what a student has done and what is still missing, in the BJS and the DOSB
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SchuelerKarte {
+    pub id: i64,
+    pub first_name: String,
+    pub last_name: String,
+    pub klasse: String,
+    pub age: i64,
+    pub gesch: char,
+    pub bjs_punkte: i64,
+    pub bjs_urkunde: BJSAbzeichen,
+    pub bjs_gruppen: Vec<KartenGruppe>,
+    pub dosb_punkte: i64,
+    pub dosb_abzeichen: DOSBAbzeichen,
+    pub dosb_gruppen: Vec<KartenGruppe>,
+}
@@ -8,6 +8,7 @@ use crate::{InternalServerf, NotFoundf};
 use crate::dosb_eval::DOSBEvaluator;
 use crate::bjs_eval::BJSEvaluator;
 use crate::model::Attempt;
+use crate::auth::roles::Role;
 
 #[derive(Debug)]
 pub struct SchuelerResultConstructor {
@@ -18,20 +19,25 @@ pub struct SchuelerResultConstructor {
     kat_groups_dosb: Vec<i64>,
 }
 
+/// the results of all students the role can see
 pub async fn search_database(
+    role: &Role,
     db: &SqlitePool,
     dosb_db: &SqlitePool,
     bjs_db: &SqlitePool
 ) -> Result<Vec<search_schema::SchuelerResult>, HttpResponse> {
 
     // get all schueler from the database
-    let all_schueler = match sqlx::query!("SELECT id, age, gesch FROM schueler;").fetch_all(db).await {
+    let all_schueler = match sqlx::query!("SELECT id, age, gesch, klasse FROM schueler;").fetch_all(db).await {
         Ok(r) => r,
         Err(e) => return Err(InternalServerf!("There was an Error getting the schueler from the database {}", e))
     };
 
     let mut schueler_data = vec![];
     for schueler in all_schueler {
+        if !role.can_see_klasse(schueler.klasse.as_deref()) {
+            continue;
+        }
         // get all attempts of the student
         let id = schueler.id.unwrap();
         let age = schueler.age.unwrap();
@@ -69,11 +75,12 @@ pub async fn search_database(
 }
 
 pub async fn search_database_extesive(
+    role: &Role,
     db: &SqlitePool,
     dosb_db: &SqlitePool,
     bjs_db: &SqlitePool
 ) -> Result<Vec<search_schema::SchuelerResultExtensive>, HttpResponse> {
-    let schueler_data = search_database(db, dosb_db, bjs_db).await?;
+    let schueler_data = search_database(role, db, dosb_db, bjs_db).await?;
     let data_result = join_all(
         schueler_data
             .into_iter()
@@ -0,0 +1,178 @@
+//! Suggests the station a student or a class should go to next
+//!
+//! A station is good if it finishes many of the missing groups of the BJS and the DOSB
+//! (`get_missing_categorys`). The queue only breaks ties, of two stations that finish as many
+//! groups the one with the shorter queue comes first. How long the queue is, is estimated by the
+//! attempts recorded at the station in the last `fortschritt::AKTIV_ZEIT` seconds.
+//!
+//! Students without an age or a gender can't be evaluated, they are skipped with a warning.
+//! Class teachers only get suggestions for the students of their class.
+use actix_web::HttpResponse;
+use log::warn;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::{InternalServerf, NotFoundf, Forbiddenf};
+use crate::auth::roles::Role;
+use crate::bjs_eval::BJSEvaluator;
+use crate::dosb_eval::DOSBEvaluator;
+use crate::fortschritt::get_kategorien_fortschritt;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StationVorschlag {
+    pub kategorie: i64,
+    pub name: String,
+    /// how many missing groups the station finishes, of the BJS and the DOSB of all students
+    pub gruppen: u32,
+    /// how many of the students need the station
+    pub schueler: u32,
+    /// attempts at the station in the last `AKTIV_ZEIT` seconds
+    pub auslastung: i64,
+}
+
+/// the stations for a student, the best first
+pub async fn get_station_vorschlag(schueler_id: i64, role: &Role, db: &SqlitePool, dosb_db: &SqlitePool, bjs_db: &SqlitePool) -> Result<Vec<StationVorschlag>, HttpResponse> {
+    let rows = match sqlx::query!(r#"SELECT id as "id!", age, gesch, klasse FROM schueler WHERE id = ?"#, schueler_id).fetch_all(db).await {
+        Ok(r) => r,
+        Err(e) => return Err(InternalServerf!("Error while fetching the schueler {} ({})", schueler_id, e))
+    };
+    if rows.iter().any(|s| !role.can_see_klasse(s.klasse.as_deref())) {
+        return Err(Forbiddenf!("The role {} may not see the student {}", role.name, schueler_id));
+    }
+    let schueler = rows.into_iter().map(|s| (s.id, s.age, s.gesch)).collect();
+    vorschlaege(schueler, db, dosb_db, bjs_db).await
+}
+
+/// the stations for a whole class, the best first
+pub async fn get_klassen_station_vorschlag(klasse: String, role: &Role, db: &SqlitePool, dosb_db: &SqlitePool, bjs_db: &SqlitePool) -> Result<Vec<StationVorschlag>, HttpResponse> {
+    if !role.can_see_klasse(Some(&klasse)) {
+        return Err(Forbiddenf!("The role {} may not see the class {}", role.name, klasse));
+    }
+    let schueler = match sqlx::query!(r#"SELECT id as "id!", age, gesch FROM schueler WHERE klasse = ?"#, klasse).fetch_all(db).await {
+        Ok(r) => r.into_iter().map(|s| (s.id, s.age, s.gesch)).collect(),
+        Err(e) => return Err(InternalServerf!("Error while fetching the schueler of {} ({})", klasse, e))
+    };
+    vorschlaege(schueler, db, dosb_db, bjs_db).await
+}
+
+async fn vorschlaege(
+    schueler: Vec<(i64, Option<i64>, Option<String>)>,
+    db: &SqlitePool,
+    dosb_db: &SqlitePool,
+    bjs_db: &SqlitePool,
+) -> Result<Vec<StationVorschlag>, HttpResponse> {
+    if schueler.is_empty() {
+        return Err(NotFoundf!("No schueler was found"));
+    }
+    let bjs_evaluator = BJSEvaluator { db: bjs_db };
+    let dosb_evaluator = DOSBEvaluator { db: dosb_db };
+
+    let mut missing = vec![];
+    for (id, age, gesch) in schueler {
+        let (age, gender) = match (age, gesch.as_ref().and_then(|g| g.chars().next())) {
+            (Some(a), Some(g)) => (a, g),
+            _ => {
+                warn!("The schueler {} has no age or gender, it is skipped in the station suggestion", id);
+                continue;
+            }
+        };
+        let done: Vec<i64> = match sqlx::query!("SELECT DISTINCT kategorieId as kategorie_id FROM versuch WHERE schuelerId = ? AND isReal = true", id)
+            .fetch_all(db).await {
+                Ok(r) => r.into_iter().map(|v| v.kategorie_id).collect(),
+                Err(e) => return Err(InternalServerf!("Error while fetching the attempts of {} ({})", id, e))
+        };
+        // one student that can't be evaluated shouldn't hide the suggestion for the whole class
+        let (bjs, dosb) = match (
+            bjs_evaluator.get_missing_categorys(age, gender, done.clone()).await,
+            dosb_evaluator.get_missing_categorys(age, gender, done).await,
+        ) {
+            (Ok(b), Ok(d)) => (b, d),
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("The missing categories of the schueler {} couldn't be evaluated ({}), it is skipped in the station suggestion", id, e.status());
+                continue;
+            }
+        };
+        missing.push((id, bjs));
+        missing.push((id, dosb));
+    }
+    if missing.is_empty() {
+        return Err(NotFoundf!("None of the schueler could be evaluated, they have no age or gender or no BJS/DOSB table"));
+    }
+
+    let kategorien: HashMap<i64, String> = match sqlx::query!("SELECT id, name FROM kategorien").fetch_all(db).await {
+        Ok(r) => r.into_iter().map(|k| (k.id, k.name.unwrap_or_default())).collect(),
+        Err(e) => return Err(InternalServerf!("Error while fetching the kategorien ({})", e))
+    };
+    let zeit = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let auslastung: HashMap<i64, i64> = get_kategorien_fortschritt(db, zeit).await?
+        .into_iter()
+        .map(|k| (k.kategorie, k.aktiv))
+        .collect();
+
+    Ok(bewerten(&missing, &kategorien, &auslastung))
+}
+
+/**
+ * Rates the stations of the event by the missing groups they finish, then by the load.
+ *
+ * `missing` has the missing categories by group of a student (the id) in the BJS or the DOSB.
+ */
+fn bewerten(missing: &[(i64, Vec<Vec<i64>>)], kategorien: &HashMap<i64, String>, auslastung: &HashMap<i64, i64>) -> Vec<StationVorschlag> {
+    let mut gruppen: HashMap<i64, u32> = HashMap::new();
+    let mut schueler: HashMap<i64, HashSet<i64>> = HashMap::new();
+    for (id, by_group) in missing {
+        for group in by_group {
+            for k in group.iter().filter(|k| kategorien.contains_key(k)) {
+                *gruppen.entry(*k).or_default() += 1;
+                schueler.entry(*k).or_default().insert(*id);
+            }
+        }
+    }
+
+    let mut vorschlaege: Vec<StationVorschlag> = gruppen.into_iter()
+        .map(|(k, g)| StationVorschlag {
+            kategorie: k,
+            name: kategorien[&k].clone(),
+            gruppen: g,
+            schueler: schueler[&k].len() as u32,
+            auslastung: auslastung.get(&k).copied().unwrap_or_default(),
+        })
+        .collect();
+    vorschlaege.sort_by(|a, b| b.gruppen.cmp(&a.gruppen)
+        .then(a.auslastung.cmp(&b.auslastung))
+        .then(a.kategorie.cmp(&b.kategorie)));
+    vorschlaege
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_stations() {
+        let kategorien: HashMap<i64, String> = [(1, "50m"), (4, "Weitsprung"), (5, "Hochsprung"), (6, "Schlagball")]
+            .iter().map(|(k, n)| (*k, n.to_string())).collect();
+        let missing = vec![
+            // bjs and dosb of the first student, kategorie 9 is not part of the event
+            (1, vec![vec![], vec![4, 5], vec![6], vec![9]]),
+            (1, vec![vec![6], vec![], vec![4], vec![]]),
+            // the second student
+            (2, vec![vec![], vec![4, 5], vec![], vec![]]),
+        ];
+        let auslastung = [(4, 12), (6, 2)].into();
+
+        let vorschlaege = bewerten(&missing, &kategorien, &auslastung);
+        let rated: Vec<(i64, u32, u32)> = vorschlaege.iter().map(|v| (v.kategorie, v.gruppen, v.schueler)).collect();
+        // weitsprung finishes the most groups, hochsprung and schlagball are sorted by the load
+        assert_eq!(rated, vec![(4, 3, 2), (5, 2, 2), (6, 2, 1)]);
+
+        // weitsprung and hochsprung finish the same group, hochsprung has the shorter queue
+        let missing = vec![(1, vec![vec![4, 5]])];
+        let vorschlaege = bewerten(&missing, &kategorien, &auslastung);
+        assert_eq!(vorschlaege[0].kategorie, 5);
+    }
+}